@@ -1,3 +1,6 @@
 fn main() {
-    tauri_build::build()
-} 
\ No newline at end of file
+    // 只构建守护进程时没有 tauri 依赖，也不需要生成界面相关的资源
+    if std::env::var_os("CARGO_FEATURE_GUI").is_some() {
+        tauri_build::build()
+    }
+}
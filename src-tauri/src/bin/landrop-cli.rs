@@ -0,0 +1,459 @@
+// 命令行客户端：在脚本和 CI 里发现设备、发送文件和文本、接收一次传输
+use landrop::config;
+use landrop::discovery::DiscoveryService;
+use landrop::events::ChannelSink;
+use landrop::transfer::{TransferService, VerificationFailed};
+use landrop::types::{Device, Presence};
+use std::io::BufRead;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage:
+  landrop-cli list [--timeout <secs>]
+  landrop-cli pair --to <name|id>
+  landrop-cli send <path>... --to <name|id>
+  landrop-cli send-text <text|-> --to <name|id>
+  landrop-cli receive [--once] [--accept-pairing]
+  landrop-cli add-peer <host[:port]>
+  landrop-cli remove-peer <host[:port]>
+
+Options:
+  --json               print machine-readable JSON
+  --timeout <secs>     how long to wait for discovery or an incoming transfer
+  --accept-pairing     when receiving, ask for the code shown on the pairing device
+  --config-dir <dir>   use another config directory";
+
+// 退出码，脚本可以据此区分失败原因
+const EXIT_OK: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_NOT_FOUND: i32 = 3;
+const EXIT_TIMEOUT: i32 = 4;
+const EXIT_VERIFICATION_FAILED: i32 = 5;
+
+// 发现广播每 5 秒一次，默认多等一点保证能收到一轮
+const DEFAULT_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(6);
+
+#[derive(Default)]
+struct Options {
+    json: bool,
+    once: bool,
+    accept_pairing: bool,
+    timeout: Option<Duration>,
+    to: Option<String>,
+    positional: Vec<String>,
+}
+
+struct Failure {
+    code: i32,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Failure {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+// 接收方报告校验失败时用单独的退出码，其余错误都是一般失败
+impl From<anyhow::Error> for Failure {
+    fn from(e: anyhow::Error) -> Self {
+        let code = if e.is::<VerificationFailed>() {
+            EXIT_VERIFICATION_FAILED
+        } else {
+            EXIT_FAILURE
+        };
+        Failure::new(code, e.to_string())
+    }
+}
+
+// 命令的结果，按 --json 决定输出 JSON 还是给人看的文本
+struct Output {
+    json: serde_json::Value,
+    text: String,
+}
+
+#[tokio::main]
+async fn main() {
+    let (command, options) = match parse_args(std::env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(message) if message.is_empty() => {
+            println!("{}", USAGE);
+            std::process::exit(EXIT_OK);
+        }
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            std::process::exit(EXIT_USAGE);
+        }
+    };
+
+    let json = options.json;
+    let code = match run(&command, options).await {
+        Ok(output) => {
+            if json {
+                println!("{}", output.json);
+            } else {
+                println!("{}", output.text);
+            }
+            EXIT_OK
+        }
+        Err(failure) => {
+            if json {
+                println!("{}", serde_json::json!({ "status": "error", "error": failure.message }));
+            } else {
+                eprintln!("Error: {}", failure.message);
+            }
+            failure.code
+        }
+    };
+    std::process::exit(code);
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(String, Options), String> {
+    let mut command = None;
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => options.json = true,
+            "--once" => options.once = true,
+            "--accept-pairing" => options.accept_pairing = true,
+            "--to" => options.to = Some(args.next().ok_or("--to needs a device name or id")?),
+            "--timeout" => {
+                let secs: u64 = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or("--timeout needs a number of seconds")?;
+                options.timeout = Some(Duration::from_secs(secs));
+            }
+            "--config-dir" => {
+                let dir = args.next().ok_or("--config-dir needs a directory")?;
+                config::set_config_dir(PathBuf::from(dir));
+            }
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ if command.is_none() => command = Some(arg),
+            _ => options.positional.push(arg),
+        }
+    }
+
+    let command = command.ok_or("Missing command")?;
+    Ok((command, options))
+}
+
+async fn run(command: &str, options: Options) -> Result<Output, Failure> {
+    match command {
+        "list" => list(options).await,
+        "pair" => pair(options).await,
+        "send" => send(options).await,
+        "send-text" => send_text(options).await,
+        "receive" => receive(options).await,
+        "add-peer" => add_peer(options).await,
+        "remove-peer" => remove_peer(options).await,
+        _ => Err(Failure::new(EXIT_USAGE, format!("Unknown command {}\n\n{}", command, USAGE))),
+    }
+}
+
+async fn list(options: Options) -> Result<Output, Failure> {
+    let mut discovery = DiscoveryService::new()?;
+    discovery.start().await?;
+    tokio::time::sleep(options.timeout.unwrap_or(DEFAULT_DISCOVERY_TIMEOUT)).await;
+    let devices = discovery.get_devices().await;
+    discovery.stop().await?;
+
+    let text = if devices.is_empty() {
+        "No devices found".to_string()
+    } else {
+        devices
+            .iter()
+            .map(|device| {
+                format!(
+                    "{}\t{}\t{}\t{}",
+                    device.id,
+                    device.name,
+                    SocketAddr::new(device.ip, device.port),
+                    presence_label(device.presence)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    Ok(Output {
+        json: serde_json::json!(devices),
+        text,
+    })
+}
+
+async fn pair(options: Options) -> Result<Output, Failure> {
+    let target = find_device(&options).await?;
+    let transfer = TransferService::new()?;
+    let code = transfer.start_pairing(&target).await?;
+
+    // 确认码必须由人核对，提示写到 stderr，不影响 JSON 输出
+    eprintln!(
+        "Pairing code {}. Confirm it matches the code shown on {} [y/N]: ",
+        code, target.name
+    );
+    let mut answer = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(|e| Failure::new(EXIT_FAILURE, e.to_string()))?;
+    let accept = matches!(answer.trim(), "y" | "Y" | "yes");
+
+    let paired = transfer.confirm_pairing(&target.id, accept).await?;
+    if !paired {
+        return Err(Failure::new(EXIT_FAILURE, format!("Pairing with {} was not confirmed", target.name)));
+    }
+    Ok(Output {
+        json: serde_json::json!({ "status": "paired", "device": target }),
+        text: format!("Paired with {}", target.name),
+    })
+}
+
+async fn send(options: Options) -> Result<Output, Failure> {
+    if options.positional.is_empty() {
+        return Err(Failure::new(EXIT_USAGE, "Nothing to send"));
+    }
+    let target = find_device(&options).await?;
+    let transfer = TransferService::new()?;
+    transfer.send_paths(&options.positional, &target).await?;
+
+    Ok(Output {
+        json: serde_json::json!({
+            "status": "sent",
+            "device": target,
+            "paths": options.positional
+        }),
+        text: format!("Sent {} item(s) to {}", options.positional.len(), target.name),
+    })
+}
+
+async fn send_text(options: Options) -> Result<Output, Failure> {
+    // 单独一个 - 表示从标准输入读取
+    let text = if options.positional == ["-"] {
+        std::io::read_to_string(std::io::stdin()).map_err(|e| Failure::new(EXIT_FAILURE, e.to_string()))?
+    } else {
+        options.positional.join(" ")
+    };
+    if text.is_empty() {
+        return Err(Failure::new(EXIT_USAGE, "Nothing to send"));
+    }
+
+    let target = find_device(&options).await?;
+    let transfer = TransferService::new()?;
+    transfer.send_text(&text, &target).await?;
+
+    Ok(Output {
+        json: serde_json::json!({ "status": "sent", "device": target, "bytes": text.len() }),
+        text: format!("Sent text to {}", target.name),
+    })
+}
+
+// 启动接收服务并自动同意已配对设备的请求；--once 时收完一次传输就退出，否则一直运行
+async fn receive(options: Options) -> Result<Output, Failure> {
+    let (events, mut event_receiver) = ChannelSink::new();
+    let mut transfer = TransferService::new()?;
+    transfer.start_server(Arc::new(events)).await?;
+    let mut discovery = DiscoveryService::new()?;
+    discovery.start().await?;
+    if !options.json {
+        eprintln!("Waiting for transfers on port {}", config::transfer_port());
+    }
+
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+    let mut received = Vec::new();
+    let result = loop {
+        let event = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline.into(), event_receiver.recv()).await {
+                Ok(event) => event,
+                Err(_) => break Err(Failure::new(EXIT_TIMEOUT, "Timed out waiting for a transfer")),
+            },
+            None => event_receiver.recv().await,
+        };
+        let Some((event, payload)) = event else {
+            break Err(Failure::new(EXIT_FAILURE, "Transfer server stopped"));
+        };
+
+        match event.as_str() {
+            "file-request" => {
+                let request_id = payload["requestId"].as_str().unwrap_or_default();
+                transfer.respond_request(request_id, true, None).await?;
+            }
+            // 配对需要人工核对确认码：没有 --accept-pairing 时一律拒绝，
+            // 否则要求输入对方屏幕上显示的确认码，完全一致才同意
+            "pair-request" => {
+                let pairing_id = payload["pairingId"].as_str().unwrap_or_default();
+                let accept = options.accept_pairing
+                    && ask_pairing_code(&payload).await? == payload["code"].as_str().unwrap_or_default();
+                if options.accept_pairing && !accept {
+                    eprintln!("Pairing code does not match, rejected");
+                }
+                transfer.respond_pairing(pairing_id, accept).await?;
+            }
+            "file-received" | "text-received" => {
+                if !options.once {
+                    print_event(&options, &event, &payload);
+                }
+                let is_text = event == "text-received";
+                received.push(payload);
+                if options.once && is_text {
+                    break Ok(());
+                }
+            }
+            "file-request-completed" if options.once => break Ok(()),
+            "file-verification-failed" => {
+                if options.once {
+                    break Err(Failure::new(
+                        EXIT_VERIFICATION_FAILED,
+                        format!("Checksum mismatch for {}", payload["fileName"].as_str().unwrap_or_default()),
+                    ));
+                }
+                print_event(&options, &event, &payload);
+            }
+            _ => {}
+        }
+    };
+    discovery.stop().await?;
+    result?;
+
+    let text = received
+        .iter()
+        .map(|item| match item["filePath"].as_str() {
+            Some(path) => format!("Received {}", path),
+            None => format!("Received text: {}", item["content"].as_str().unwrap_or_default()),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok(Output {
+        json: serde_json::json!({ "status": "received", "items": received }),
+        text,
+    })
+}
+
+// 手动添加组播找不到的设备，之后 list、send 等命令都能发现它
+async fn add_peer(options: Options) -> Result<Output, Failure> {
+    let (host, port) = peer_address(&options)?;
+    let transfer = TransferService::new()?;
+    let device = transfer.add_static_peer(&host, port).await?;
+
+    Ok(Output {
+        json: serde_json::json!({ "status": "added", "host": host, "port": port, "device": device }),
+        text: format!("Added {} ({}) at {}:{}", device.name, device.id, host, port),
+    })
+}
+
+async fn remove_peer(options: Options) -> Result<Output, Failure> {
+    let (host, port) = peer_address(&options)?;
+    let transfer = TransferService::new()?;
+    transfer.remove_static_peer(&host, port).await?;
+
+    Ok(Output {
+        json: serde_json::json!({ "status": "removed", "host": host, "port": port }),
+        text: format!("Removed {}:{}", host, port),
+    })
+}
+
+// host、host:port、IPv6 地址或 [IPv6]:port；不写端口时用默认传输端口
+fn peer_address(options: &Options) -> Result<(String, u16), Failure> {
+    let [address] = options.positional.as_slice() else {
+        return Err(Failure::new(EXIT_USAGE, "Expected one <host[:port]>"));
+    };
+    if let Ok(address) = address.parse::<SocketAddr>() {
+        return Ok((address.ip().to_string(), address.port()));
+    }
+    if address.parse::<IpAddr>().is_ok() {
+        return Ok((address.clone(), config::DEFAULT_TRANSFER_PORT));
+    }
+    match address.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port
+                .parse()
+                .map_err(|_| Failure::new(EXIT_USAGE, format!("Invalid port in {}", address)))?;
+            Ok((host.to_string(), port))
+        }
+        None => Ok((address.clone(), config::DEFAULT_TRANSFER_PORT)),
+    }
+}
+
+fn presence_label(presence: Presence) -> &'static str {
+    match presence {
+        Presence::Online => "online",
+        Presence::Stale => "stale",
+        Presence::Offline => "offline",
+    }
+}
+
+// 提示写到 stderr，不影响 JSON 输出；读标准输入会阻塞，放到单独的线程里
+async fn ask_pairing_code(payload: &serde_json::Value) -> Result<String, Failure> {
+    eprintln!(
+        "Pairing requested by {}. Enter the code shown on that device: ",
+        payload["device"]["name"].as_str().unwrap_or_default()
+    );
+    tokio::task::spawn_blocking(|| {
+        let mut answer = String::new();
+        std::io::stdin().lock().read_line(&mut answer).map(|_| answer.trim().to_string())
+    })
+    .await
+    .map_err(|e| Failure::new(EXIT_FAILURE, e.to_string()))?
+    .map_err(|e| Failure::new(EXIT_FAILURE, e.to_string()))
+}
+
+fn print_event(options: &Options, event: &str, payload: &serde_json::Value) {
+    if options.json {
+        println!("{}", serde_json::json!({ "event": event, "payload": payload }));
+    } else {
+        match payload["filePath"].as_str() {
+            Some(path) => println!("{}: {}", event, path),
+            None => println!("{}: {}", event, payload),
+        }
+    }
+}
+
+// 按 id 或名称（不区分大小写）在局域网里找目标设备，找到就立即返回
+async fn find_device(options: &Options) -> Result<Device, Failure> {
+    let target = options
+        .to
+        .as_deref()
+        .ok_or_else(|| Failure::new(EXIT_USAGE, "Missing --to <name|id>"))?;
+
+    let mut discovery = DiscoveryService::new()?;
+    discovery.start().await?;
+    let deadline = Instant::now() + options.timeout.unwrap_or(DEFAULT_DISCOVERY_TIMEOUT);
+
+    let result = loop {
+        // 已经道别或者长时间没有广播的设备连不上，不作为目标
+        let devices: Vec<Device> = discovery
+            .get_devices()
+            .await
+            .into_iter()
+            .filter(|device| device.presence != Presence::Offline)
+            .collect();
+        if let Some(device) = devices.iter().find(|device| device.id == target) {
+            break Ok(device.clone());
+        }
+        let matches: Vec<&Device> = devices
+            .iter()
+            .filter(|device| device.name.eq_ignore_ascii_case(target))
+            .collect();
+        match matches.as_slice() {
+            [device] => break Ok((*device).clone()),
+            [] if Instant::now() < deadline => tokio::time::sleep(Duration::from_millis(200)).await,
+            [] => break Err(Failure::new(EXIT_NOT_FOUND, format!("Device {} not found", target))),
+            _ => {
+                break Err(Failure::new(
+                    EXIT_NOT_FOUND,
+                    format!("{} devices are named {}, use the device id instead", matches.len(), target),
+                ))
+            }
+        }
+    };
+
+    discovery.stop().await?;
+    result
+}
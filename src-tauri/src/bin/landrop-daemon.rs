@@ -0,0 +1,224 @@
+// 无界面的守护进程：运行设备发现和接收服务，事件写进日志，适合服务器和 NAS
+use anyhow::Result;
+use landrop::config;
+use landrop::discovery::DiscoveryService;
+use landrop::events::ChannelSink;
+use landrop::pairing;
+use landrop::transfer::TransferService;
+use landrop::types::Device;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const USAGE: &str = "Usage: landrop-daemon [--config-dir <dir>]";
+
+// 守护进程自己的配置，放在配置目录的 daemon.json；端口、接收目录等仍然读 settings.json
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct DaemonConfig {
+    // 没有人能点确认，已配对设备发来的文件默认直接接收
+    auto_accept: bool,
+    // 配对本该由人核对确认码，默认拒绝；打开后局域网内任何设备都能与本机配对。
+    // 需要配对时在本机用 landrop-cli pair 指向同一配置目录，守护进程不用重启就能认出新设备
+    accept_pairing: bool,
+    log_file: Option<PathBuf>,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        DaemonConfig {
+            auto_accept: true,
+            accept_pairing: false,
+            log_file: None,
+        }
+    }
+}
+
+impl DaemonConfig {
+    // 第一次运行时写出默认配置，方便直接修改
+    fn load() -> Result<Self> {
+        let path = config::config_dir().join("daemon.json");
+        match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|e| anyhow::anyhow!("Invalid {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let daemon_config = DaemonConfig::default();
+                std::fs::create_dir_all(config::config_dir())?;
+                std::fs::write(&path, serde_json::to_vec_pretty(&daemon_config)?)?;
+                Ok(daemon_config)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// 日志同时写到标准输出和可选的日志文件
+struct Logger {
+    file: Option<Mutex<File>>,
+}
+
+impl Logger {
+    fn open(path: Option<&Path>) -> Result<Self> {
+        let file = match path {
+            Some(path) => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                Some(Mutex::new(OpenOptions::new().create(true).append(true).open(path)?))
+            }
+            None => None,
+        };
+        Ok(Logger { file })
+    }
+
+    fn log(&self, message: &str) {
+        let line = format!("[{}] {}", pairing::now_secs(), message);
+        println!("{}", line);
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config-dir" => {
+                let dir = args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?;
+                config::set_config_dir(PathBuf::from(dir));
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => return Err(anyhow::anyhow!("Unknown argument {}\n{}", arg, USAGE)),
+        }
+    }
+
+    let daemon_config = DaemonConfig::load()?;
+    let logger = Logger::open(daemon_config.log_file.as_deref())?;
+    logger.log(&format!("Using config directory {}", config::config_dir().display()));
+
+    let (events, mut event_receiver) = ChannelSink::new();
+    let mut transfer = TransferService::new()?;
+    transfer.start_server(Arc::new(events)).await?;
+
+    let mut discovery = DiscoveryService::new()?;
+    discovery.start().await?;
+
+    let device = Device::current()?;
+    logger.log(&format!(
+        "Daemon started as {} ({}) on port {}",
+        device.name, device.id, device.port
+    ));
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            Some((event, payload)) = event_receiver.recv() => {
+                handle_event(&transfer, &daemon_config, &logger, &event, &payload).await;
+            }
+            _ = &mut shutdown => break,
+        }
+    }
+
+    logger.log("Shutting down");
+    discovery.stop().await?;
+    Ok(())
+}
+
+async fn handle_event(
+    transfer: &TransferService,
+    daemon_config: &DaemonConfig,
+    logger: &Logger,
+    event: &str,
+    payload: &serde_json::Value,
+) {
+    match event {
+        "file-request" => {
+            let request_id = payload["requestId"].as_str().unwrap_or_default();
+            logger.log(&format!(
+                "File request from {}: {} ({} files, {} bytes), {}",
+                payload["sender"]["name"].as_str().unwrap_or_default(),
+                payload["fileName"].as_str().unwrap_or_default(),
+                payload["fileCount"],
+                payload["fileSize"],
+                if daemon_config.auto_accept { "accepting" } else { "declining" }
+            ));
+            if let Err(e) = transfer
+                .respond_request(request_id, daemon_config.auto_accept, None)
+                .await
+            {
+                logger.log(&format!("Failed to respond to file request: {}", e));
+            }
+        }
+        "pair-request" => {
+            let device_id = payload["device"]["id"].as_str().unwrap_or_default();
+            logger.log(&format!(
+                "Pairing requested by {} ({}) with code {}, {}",
+                payload["device"]["name"].as_str().unwrap_or_default(),
+                device_id,
+                payload["code"].as_str().unwrap_or_default(),
+                if daemon_config.accept_pairing {
+                    "accepting"
+                } else {
+                    "declining because accept_pairing is off"
+                }
+            ));
+            if let Err(e) = transfer
+                .respond_pairing(payload["pairingId"].as_str().unwrap_or_default(), daemon_config.accept_pairing)
+                .await
+            {
+                logger.log(&format!("Failed to respond to pairing: {}", e));
+            }
+        }
+        "file-received" => logger.log(&format!(
+            "Received {} from {} -> {}",
+            payload["fileName"].as_str().unwrap_or_default(),
+            payload["sender"]["name"].as_str().unwrap_or_default(),
+            payload["filePath"].as_str().unwrap_or_default()
+        )),
+        "file-verification-failed" => logger.log(&format!(
+            "Checksum mismatch for {} from {}, file discarded",
+            payload["fileName"].as_str().unwrap_or_default(),
+            payload["sender"]["name"].as_str().unwrap_or_default()
+        )),
+        "text-received" => logger.log(&format!(
+            "Text from {}: {}",
+            payload["sender"]["name"].as_str().unwrap_or_default(),
+            payload["content"].as_str().unwrap_or_default()
+        )),
+        _ => logger.log(&format!("{}: {}", event, payload)),
+    }
+}
+
+// Ctrl+C 或 systemd 发来的 SIGTERM 都会正常退出
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
@@ -0,0 +1,293 @@
+use crate::types::{ConflictPolicy, RoutingRule, StaticPeer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::OnceLock;
+
+pub const DEFAULT_TRANSFER_PORT: u16 = 8080;
+pub const DEFAULT_DISCOVERY_PORT: u16 = 8889;
+
+// 传输服务实际监听的端口，绑定成功之前为 0，此时按配置的端口对外声明
+static BOUND_TRANSFER_PORT: AtomicU16 = AtomicU16::new(0);
+
+static SETTINGS_ERROR_REPORTED: AtomicBool = AtomicBool::new(false);
+
+static DEVICE_ID: OnceLock<String> = OnceLock::new();
+
+static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+// 所有需要持久化的配置都放在系统配置目录下的 landrop 子目录，守护进程可以另外指定
+pub fn config_dir() -> PathBuf {
+    if let Some(dir) = CONFIG_DIR.get() {
+        return dir.clone();
+    }
+    dirs::config_dir()
+        .unwrap_or_else(|| std::env::current_dir().unwrap())
+        .join("landrop")
+}
+
+// 必须在读取任何配置之前调用，之后再调用不生效
+pub fn set_config_dir(dir: PathBuf) {
+    let _ = CONFIG_DIR.set(dir);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // 默认只走 TLS；打开后服务端改用明文 HTTP，并允许连接没有证书指纹的旧版本设备
+    pub allow_plaintext: bool,
+    pub conflict_policy: ConflictPolicy,
+    // 未设置时使用系统下载目录
+    pub receive_dir: Option<PathBuf>,
+    pub routing_rules: Vec<RoutingRule>,
+    // 被占用时会自动往后换一个空闲端口；修改后重启生效
+    pub transfer_port: u16,
+    // 所有设备必须一致，不做自动更换
+    pub discovery_port: u16,
+    // 两种发现方式可以同时开启：自有的 UDP 组播协议，以及标准的 mDNS/DNS-SD
+    pub udp_discovery: bool,
+    pub mdns_discovery: bool,
+    // 组播不通的网段：每轮广播时再向这些 IP、主机名或 IPv4 网段（如 10.1.2.0/24）单播 Ping
+    pub unicast_targets: Vec<String>,
+    pub static_peers: Vec<StaticPeer>,
+    // 为空时使用主机名
+    pub device_name: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            allow_plaintext: false,
+            conflict_policy: ConflictPolicy::default(),
+            receive_dir: None,
+            routing_rules: Vec::new(),
+            transfer_port: DEFAULT_TRANSFER_PORT,
+            discovery_port: DEFAULT_DISCOVERY_PORT,
+            udp_discovery: true,
+            mdns_discovery: true,
+            unicast_targets: Vec::new(),
+            static_peers: Vec::new(),
+            device_name: None,
+        }
+    }
+}
+
+impl Settings {
+    // 只读的地方文件有误就用默认值，错误只打印一次，免得每轮发现广播都刷屏
+    pub fn load() -> Self {
+        Self::try_load().unwrap_or_else(|e| {
+            if !SETTINGS_ERROR_REPORTED.swap(true, Ordering::Relaxed) {
+                eprintln!("{}, using default settings", e);
+            }
+            Settings::default()
+        })
+    }
+
+    // 要写回的地方必须用这个：文件有误时报错，而不是拿默认值覆盖掉用户的修改
+    pub fn try_load() -> Result<Self> {
+        let path = config_dir().join("settings.json");
+        match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|e| anyhow::anyhow!("Invalid {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Settings::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let dir = config_dir();
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("settings.json"), serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn receive_dir(&self) -> PathBuf {
+        self.receive_dir
+            .clone()
+            .or_else(dirs::download_dir)
+            .unwrap_or_else(|| std::env::current_dir().unwrap())
+    }
+
+    // 按分流规则决定文件落在哪个目录，没有规则命中就用默认接收目录
+    pub fn route(&self, sender_id: &str, file_name: &str, mime_type: &str) -> PathBuf {
+        self.routing_rules
+            .iter()
+            .find(|rule| rule_matches(rule, sender_id, file_name, mime_type))
+            .map(|rule| rule.directory.clone())
+            .unwrap_or_else(|| self.receive_dir())
+    }
+}
+
+// 本机的设备 id 在首次启动时生成并保存在配置目录，所有服务共用同一个
+pub fn device_id() -> String {
+    DEVICE_ID.get_or_init(load_or_create_device_id).clone()
+}
+
+fn load_or_create_device_id() -> String {
+    let path = config_dir().join("device_id");
+    if let Ok(id) = std::fs::read_to_string(&path) {
+        let id = id.trim();
+        if uuid::Uuid::parse_str(id).is_ok() {
+            return id.to_string();
+        }
+    }
+
+    let id = uuid::Uuid::new_v4().to_string();
+    if let Err(e) = std::fs::create_dir_all(config_dir()).and_then(|_| std::fs::write(&path, &id)) {
+        eprintln!("Failed to save device id: {}", e);
+    }
+    id
+}
+
+// 密钥类文件只允许本人读写：先以 0600 写到临时文件再改名，任何时刻都不会以默认权限出现在磁盘上
+pub fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&temp_path)?;
+    // 上次残留的临时文件可能是别的权限，mode 只对新建的文件生效
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+// 用户设置的名称优先，否则用主机名
+pub fn device_name() -> Result<String> {
+    if let Some(name) = Settings::load().device_name {
+        let name = name.trim();
+        if !name.is_empty() {
+            return Ok(name.to_string());
+        }
+    }
+
+    Ok(hostname::get()
+        .map_err(|e| anyhow::anyhow!("Failed to get hostname: {}", e))?
+        .to_string_lossy()
+        .to_string())
+}
+
+pub fn transfer_port() -> u16 {
+    match BOUND_TRANSFER_PORT.load(Ordering::Relaxed) {
+        0 => Settings::load().transfer_port,
+        port => port,
+    }
+}
+
+pub fn set_transfer_port(port: u16) {
+    BOUND_TRANSFER_PORT.store(port, Ordering::Relaxed);
+}
+
+fn rule_matches(rule: &RoutingRule, sender_id: &str, file_name: &str, mime_type: &str) -> bool {
+    if let Some(expected) = &rule.sender_id {
+        if expected != sender_id {
+            return false;
+        }
+    }
+
+    if let Some(expected) = &rule.mime_type {
+        let matched = match expected.strip_suffix("/*") {
+            Some(category) => mime_type
+                .split('/')
+                .next()
+                .is_some_and(|actual| actual.eq_ignore_ascii_case(category)),
+            None => expected.eq_ignore_ascii_case(mime_type),
+        };
+        if !matched {
+            return false;
+        }
+    }
+
+    if let Some(expected) = &rule.extension {
+        let expected = expected.trim_start_matches('.');
+        let matched = Path::new(file_name)
+            .extension()
+            .is_some_and(|actual| actual.to_string_lossy().eq_ignore_ascii_case(expected));
+        if !matched {
+            return false;
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(sender_id: Option<&str>, mime_type: Option<&str>, extension: Option<&str>) -> RoutingRule {
+        RoutingRule {
+            id: String::new(),
+            sender_id: sender_id.map(str::to_string),
+            mime_type: mime_type.map(str::to_string),
+            extension: extension.map(str::to_string),
+            directory: PathBuf::from("routed"),
+        }
+    }
+
+    #[test]
+    fn matches_mime_category() {
+        let images = rule(None, Some("image/*"), None);
+        assert!(rule_matches(&images, "a", "photo.png", "image/png"));
+        assert!(rule_matches(&images, "a", "photo.JPG", "IMAGE/jpeg"));
+        assert!(!rule_matches(&images, "a", "notes.txt", "text/plain"));
+        assert!(!rule_matches(&images, "a", "imagery.bin", "imagery/x"));
+    }
+
+    #[test]
+    fn matches_exact_mime_type() {
+        let pdf = rule(None, Some("application/pdf"), None);
+        assert!(rule_matches(&pdf, "a", "paper.pdf", "application/pdf"));
+        assert!(!rule_matches(&pdf, "a", "paper.zip", "application/zip"));
+    }
+
+    #[test]
+    fn matches_extension_case_insensitively() {
+        let logs = rule(None, None, Some(".log"));
+        assert!(rule_matches(&logs, "a", "build/output.LOG", "text/plain"));
+        assert!(!rule_matches(&logs, "a", "output.log.gz", "application/gzip"));
+        assert!(!rule_matches(&logs, "a", "log", "application/octet-stream"));
+    }
+
+    #[test]
+    fn requires_every_condition() {
+        let rule = rule(Some("laptop"), Some("image/*"), Some("png"));
+        assert!(rule_matches(&rule, "laptop", "a.png", "image/png"));
+        assert!(!rule_matches(&rule, "phone", "a.png", "image/png"));
+        assert!(!rule_matches(&rule, "laptop", "a.jpg", "image/jpeg"));
+    }
+
+    #[test]
+    fn route_uses_first_matching_rule() {
+        let settings = Settings {
+            receive_dir: Some(PathBuf::from("downloads")),
+            routing_rules: vec![
+                RoutingRule { directory: PathBuf::from("pictures"), ..rule(None, Some("image/*"), None) },
+                RoutingRule { directory: PathBuf::from("png"), ..rule(None, None, Some("png")) },
+            ],
+            ..Settings::default()
+        };
+        assert_eq!(settings.route("a", "a.png", "image/png"), PathBuf::from("pictures"));
+        assert_eq!(settings.route("a", "a.txt", "text/plain"), PathBuf::from("downloads"));
+    }
+}
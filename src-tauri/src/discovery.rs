@@ -1,18 +1,151 @@
-use crate::types::{Device, DiscoveryMessage, DiscoveryMessageType};
+use crate::config::{self, Settings};
+use crate::mdns::MdnsBackend;
+use crate::peers::StaticPeerBackend;
+use crate::types::{Device, DeviceDelta, DiscoveryMessage, DiscoveryMessageType, Presence};
 use anyhow::Result;
+use if_addrs::IfAddr;
+use socket2::{Domain, Protocol, Socket, Type};
 use std::collections::HashMap;
-use std::net::{SocketAddr, UdpSocket};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::sync::{broadcast, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, Mutex, Notify};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 
-const DISCOVERY_PORT: u16 = 8889;
-const MULTICAST_ADDR: &str = "239.255.255.250";
+const MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+
+// 链路本地范围的组播组，对应 IPv4 的 239.255.255.250
+const MULTICAST_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xc);
+
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5);
+
+// 检查网卡变化的间隔，新网卡出现后会立即广播一次
+const INTERFACE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// 漏掉两次广播算作可能离线，漏掉五次以上算作离线
+const STALE_AFTER: Duration = Duration::from_secs(12);
+const OFFLINE_AFTER: Duration = Duration::from_secs(30);
+
+// 单播 Ping 的网段最多展开这么多个地址，相当于 /22
+const UNICAST_MAX_HOSTS: u32 = 1024;
+
+// 离线设备继续保留一段时间再从列表里删掉，方便用户看到它刚刚还在
+const OFFLINE_RETENTION: Duration = Duration::from_secs(300);
+
+const PRESENCE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// 超过这个时间还没收到 Pong 的 Ping 不再计算往返时间
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+// 过期判断用本机的单调时钟，不受对方时钟偏差或本机改时间的影响
+struct DiscoveredDevice {
+    device: Device,
+    received: Instant,
+    // 收到了 Goodbye
+    departed: bool,
+}
+
+impl DiscoveredDevice {
+    fn presence(&self) -> Presence {
+        let elapsed = self.received.elapsed();
+        if self.departed || elapsed > OFFLINE_AFTER {
+            Presence::Offline
+        } else if elapsed > STALE_AFTER {
+            Presence::Stale
+        } else {
+            Presence::Online
+        }
+    }
+}
+
+// 发现后端：各自用自己的协议发布本机、寻找其他设备，找到的设备交给 DiscoverySink 汇总。
+// 后台任务在 cancel 触发后收尾退出（发 Goodbye、注销服务、释放端口），返回的句柄由 stop 等待
+pub trait DiscoveryBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn start(&self, device: Device, sink: DiscoverySink, cancel: CancellationToken) -> Result<Vec<JoinHandle<()>>>;
+    // 立即探测一轮，不等下一个周期；不支持主动探测的后端忽略
+    fn refresh(&self) {}
+}
+
+// 所有后端共用的设备表，同一台设备不管从哪个后端发现都按 id 合并成一条
+#[derive(Clone)]
+pub struct DiscoverySink {
+    own_id: String,
+    discovered_devices: Arc<Mutex<HashMap<String, DiscoveredDevice>>>,
+    device_sender: broadcast::Sender<Vec<Device>>,
+}
+
+impl DiscoverySink {
+    // 多播会回环，自己发出的广播和发布的服务也会收到
+    pub fn is_self(&self, device_id: &str) -> bool {
+        device_id == self.own_id
+    }
+
+    // source 是报文的来源地址；mDNS 这类拿不到来源地址的后端传 None，沿用已知的首选地址
+    pub async fn device_seen(&self, device: Device, source: Option<SocketAddr>) {
+        if self.is_self(&device.id) {
+            return;
+        }
+
+        let mut devices = self.discovered_devices.lock().await;
+        let known = devices.get(&device.id).map(|entry| &entry.device);
+        // 只有 Pong 带着往返时间，广播和 mDNS 记录沿用上一次测到的值
+        let latency_ms = device.latency_ms.or(known.and_then(|known| known.latency_ms));
+        let mut device = match source {
+            Some(source) => device.observed_at(source, known),
+            None => device.merged_with(known),
+        };
+        // 对方自报的 last_seen 用的是对方的时钟，改成本机收到的时间
+        device.last_seen = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        device.presence = Presence::Online;
+        device.latency_ms = latency_ms;
+
+        devices.insert(
+            device.id.clone(),
+            DiscoveredDevice {
+                device,
+                received: Instant::now(),
+                departed: false,
+            },
+        );
+        let _ = self.device_sender.send(device_list(&devices));
+    }
+
+    // 还没有离线的设备，供后端主动探测
+    pub async fn known_devices(&self) -> Vec<Device> {
+        let devices = self.discovered_devices.lock().await;
+        devices
+            .values()
+            .filter(|entry| entry.presence() != Presence::Offline)
+            .map(|entry| entry.device.clone())
+            .collect()
+    }
+
+    // 对方道别后先标记为离线，过一段时间再由清理任务删除
+    pub async fn device_left(&self, device_id: &str) {
+        let mut devices = self.discovered_devices.lock().await;
+        let Some(entry) = devices.get_mut(device_id) else {
+            return;
+        };
+        entry.received = Instant::now();
+        entry.departed = true;
+        entry.device.presence = Presence::Offline;
+        let _ = self.device_sender.send(device_list(&devices));
+    }
+}
 
 pub struct DiscoveryService {
     device: Device,
-    discovered_devices: Arc<Mutex<HashMap<String, Device>>>,
-    running: Arc<Mutex<bool>>,
+    backends: Vec<Box<dyn DiscoveryBackend>>,
+    discovered_devices: Arc<Mutex<HashMap<String, DiscoveredDevice>>>,
+    // 运行中才有；每次 start 换一个新的，停止后的任务不会被下一次启动误用
+    cancel: Option<CancellationToken>,
+    tasks: Vec<JoinHandle<()>>,
     device_sender: broadcast::Sender<Vec<Device>>,
 }
 
@@ -20,121 +153,210 @@ impl DiscoveryService {
     pub fn new() -> Result<Self> {
         let device = Device::current()?;
         let (device_sender, _) = broadcast::channel(100);
-        
+        let settings = Settings::load();
+
+        let mut backends: Vec<Box<dyn DiscoveryBackend>> = Vec::new();
+        if settings.udp_discovery {
+            backends.push(Box::new(UdpBackend::new(settings.discovery_port)));
+        }
+        if settings.mdns_discovery {
+            backends.push(Box::new(MdnsBackend));
+        }
+        // 没有手动添加的设备时什么也不做，添加后不用重启发现服务
+        backends.push(Box::new(StaticPeerBackend::default()));
+
         Ok(DiscoveryService {
             device,
+            backends,
             discovered_devices: Arc::new(Mutex::new(HashMap::new())),
-            running: Arc::new(Mutex::new(false)),
+            cancel: None,
+            tasks: Vec::new(),
             device_sender,
         })
     }
 
+    // 在 start 之前调用才会生效
+    pub fn add_backend(&mut self, backend: Box<dyn DiscoveryBackend>) {
+        self.backends.push(backend);
+    }
+
     pub async fn start(&mut self) -> Result<()> {
-        let mut running = self.running.lock().await;
-        if *running {
+        if self.cancel.is_some() {
             return Ok(());
         }
-        *running = true;
-        drop(running);
-
-        // 启动发现监听器
-        let discovered_devices = self.discovered_devices.clone();
-        let device_sender = self.device_sender.clone();
-        let running_clone = self.running.clone();
-        
-        tokio::spawn(async move {
-            if let Err(e) = Self::discovery_listener(discovered_devices, device_sender, running_clone).await {
-                eprintln!("Discovery listener error: {}", e);
-            }
-        });
 
-        // 启动广播器
-        let device = self.device.clone();
-        let running_clone = self.running.clone();
-        
-        tokio::spawn(async move {
-            if let Err(e) = Self::discovery_broadcaster(device, running_clone).await {
-                eprintln!("Discovery broadcaster error: {}", e);
+        // 某个后端起不来（比如端口被占用、组播被禁止）时其余后端照常工作
+        let cancel = CancellationToken::new();
+        let sink = DiscoverySink {
+            own_id: self.device.id.clone(),
+            discovered_devices: self.discovered_devices.clone(),
+            device_sender: self.device_sender.clone(),
+        };
+        let mut tasks = Vec::new();
+        for backend in &self.backends {
+            match backend.start(self.device.clone(), sink.clone(), cancel.clone()) {
+                Ok(handles) => tasks.extend(handles),
+                Err(e) => eprintln!("{} discovery failed to start: {}", backend.name(), e),
             }
-        });
+        }
+        if tasks.is_empty() {
+            return Err(anyhow::anyhow!("No discovery backend could be started"));
+        }
 
         // 启动设备清理任务
         let discovered_devices = self.discovered_devices.clone();
         let device_sender = self.device_sender.clone();
-        let running_clone = self.running.clone();
-        
-        tokio::spawn(async move {
-            Self::device_cleanup_task(discovered_devices, device_sender, running_clone).await;
-        });
+        let cleanup_cancel = cancel.clone();
+        tasks.push(tokio::spawn(async move {
+            Self::device_cleanup_task(discovered_devices, device_sender, cleanup_cancel).await;
+        }));
 
-        println!("Discovery service started");
+        self.cancel = Some(cancel);
+        self.tasks = tasks;
+        eprintln!("Discovery service started");
         Ok(())
     }
 
+    // 等所有后台任务退出后才返回，端口已经释放，可以马上再次 start
     pub async fn stop(&mut self) -> Result<()> {
-        let mut running = self.running.lock().await;
-        *running = false;
-        println!("Discovery service stopped");
+        let Some(cancel) = self.cancel.take() else {
+            return Ok(());
+        };
+        cancel.cancel();
+        for result in futures::future::join_all(self.tasks.drain(..)).await {
+            if let Err(e) = result {
+                eprintln!("Discovery task failed: {}", e);
+            }
+        }
+
+        // 停止期间收不到任何报文，旧的设备表没有意义，重新启动时从空表开始
+        let mut devices = self.discovered_devices.lock().await;
+        devices.clear();
+        let _ = self.device_sender.send(Vec::new());
+        drop(devices);
+
+        eprintln!("Discovery service stopped");
         Ok(())
     }
 
+    // 让各后端马上探测一轮，结果照常通过 subscribe_devices 送达
+    pub fn refresh_devices(&self) {
+        for backend in &self.backends {
+            backend.refresh();
+        }
+    }
+
     pub async fn get_devices(&self) -> Vec<Device> {
         let devices = self.discovered_devices.lock().await;
-        devices.values().cloned().collect()
+        device_list(&devices)
     }
 
-    async fn discovery_listener(
-        discovered_devices: Arc<Mutex<HashMap<String, Device>>>,
+    // 按最近一次收到报文的时间更新在线状态，离线太久的设备从列表里删掉
+    async fn device_cleanup_task(
+        discovered_devices: Arc<Mutex<HashMap<String, DiscoveredDevice>>>,
         device_sender: broadcast::Sender<Vec<Device>>,
-        running: Arc<Mutex<bool>>,
+        cancel: CancellationToken,
+    ) {
+        loop {
+            let mut devices = discovered_devices.lock().await;
+            let count = devices.len();
+            devices.retain(|_, entry| entry.received.elapsed() <= OFFLINE_AFTER + OFFLINE_RETENTION);
+
+            let mut changed = devices.len() != count;
+            for entry in devices.values_mut() {
+                let presence = entry.presence();
+                if entry.device.presence != presence {
+                    entry.device.presence = presence;
+                    changed = true;
+                }
+            }
+
+            // 最后一台设备过期时也要通知，否则订阅方会一直留着它
+            if changed {
+                let _ = device_sender.send(device_list(&devices));
+            }
+            
+            drop(devices);
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = tokio::time::sleep(PRESENCE_CHECK_INTERVAL) => {}
+            }
+        }
+    }
+
+    pub fn subscribe_devices(&self) -> broadcast::Receiver<Vec<Device>> {
+        self.device_sender.subscribe()
+    }
+}
+
+// 自有的 JSON over UDP 组播协议
+pub struct UdpBackend {
+    port: u16,
+    refresh: Arc<Notify>,
+}
+
+impl UdpBackend {
+    pub fn new(port: u16) -> Self {
+        UdpBackend {
+            port,
+            refresh: Arc::new(Notify::new()),
+        }
+    }
+
+    async fn discovery_listener(
+        socket: Arc<UdpSocket>,
+        port: u16,
+        pings: Arc<std::sync::Mutex<PendingPings>>,
+        sink: DiscoverySink,
+        cancel: CancellationToken,
     ) -> Result<()> {
-        let socket = UdpSocket::bind(format!("0.0.0.0:{}", DISCOVERY_PORT))?;
-        socket.set_nonblocking(true)?;
-        
-        let mut buf = [0u8; 1024];
+        let mut buf = [0u8; 4096];
         
-        while *running.lock().await {
-            match socket.recv_from(&mut buf) {
+        loop {
+            let received = tokio::select! {
+                _ = cancel.cancelled() => break,
+                received = socket.recv_from(&mut buf) => received,
+            };
+            match received {
                 Ok((size, addr)) => {
                     if let Ok(message) = serde_json::from_slice::<DiscoveryMessage>(&buf[..size]) {
+                        if sink.is_self(&message.device.id) {
+                            continue;
+                        }
+
                         match message.message_type {
-                            DiscoveryMessageType::Announce | DiscoveryMessageType::Ping => {
-                                let mut devices = discovered_devices.lock().await;
-                                devices.insert(message.device.id.clone(), message.device.clone());
-                                let device_list: Vec<Device> = devices.values().cloned().collect();
-                                let _ = device_sender.send(device_list);
-                                
-                                // 响应 Ping
-                                if matches!(message.message_type, DiscoveryMessageType::Ping) {
-                                    let current_device = Device::current().unwrap_or_else(|_| message.device.clone());
-                                    let pong_message = DiscoveryMessage {
-                                        device: current_device,
-                                        message_type: DiscoveryMessageType::Pong,
-                                    };
-                                    
-                                    if let Ok(data) = serde_json::to_vec(&pong_message) {
-                                        let _ = socket.send_to(&data, addr);
-                                    }
-                                }
+                            DiscoveryMessageType::Announce => {
+                                sink.device_seen(message.device, Some(addr)).await;
                             }
                             DiscoveryMessageType::Pong => {
-                                let mut devices = discovered_devices.lock().await;
-                                devices.insert(message.device.id.clone(), message.device);
-                                let device_list: Vec<Device> = devices.values().cloned().collect();
-                                let _ = device_sender.send(device_list);
+                                let mut device = message.device;
+                                device.latency_ms = message
+                                    .nonce
+                                    .and_then(|nonce| pings.lock().ok()?.round_trip(nonce));
+                                sink.device_seen(device, Some(addr)).await;
                             }
-                            DiscoveryMessageType::Goodbye => {
-                                let mut devices = discovered_devices.lock().await;
-                                devices.remove(&message.device.id);
-                                let device_list: Vec<Device> = devices.values().cloned().collect();
-                                let _ = device_sender.send(device_list);
+                            DiscoveryMessageType::Ping => {
+                                sink.device_seen(message.device.clone(), Some(addr)).await;
+
+                                // 响应 Ping
+                                let current_device = Device::current().unwrap_or(message.device);
+                                let pong_message = DiscoveryMessage {
+                                    device: current_device,
+                                    message_type: DiscoveryMessageType::Pong,
+                                    nonce: message.nonce,
+                                };
+                                
+                                // 组播的 Ping 从临时端口发出，Pong 回到对方的发现端口才会被收到
+                                let mut reply_to = addr;
+                                reply_to.set_port(port);
+                                if let Ok(data) = serde_json::to_vec(&pong_message) {
+                                    let _ = socket.send_to(&data, reply_to).await;
+                                }
                             }
+                            DiscoveryMessageType::Goodbye => sink.device_left(&message.device.id).await,
                         }
                     }
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    tokio::time::sleep(Duration::from_millis(10)).await;
-                }
                 Err(e) => {
                     eprintln!("UDP receive error: {}", e);
                 }
@@ -144,73 +366,546 @@ impl DiscoveryService {
         Ok(())
     }
 
-    async fn discovery_broadcaster(device: Device, running: Arc<Mutex<bool>>) -> Result<()> {
-        let socket = UdpSocket::bind("0.0.0.0:0")?;
-        socket.set_broadcast(true)?;
-        
-        let multicast_addr: SocketAddr = format!("{}:{}", MULTICAST_ADDR, DISCOVERY_PORT).parse()?;
-        
-        while *running.lock().await {
-            let announce_message = DiscoveryMessage {
-                device: device.clone(),
-                message_type: DiscoveryMessageType::Announce,
-            };
-            
-            if let Ok(data) = serde_json::to_vec(&announce_message) {
-                let _ = socket.send_to(&data, multicast_addr);
+    async fn discovery_broadcaster(
+        mut device: Device,
+        mut interfaces: MulticastInterfaces,
+        pings: Arc<std::sync::Mutex<PendingPings>>,
+        refresh: Arc<Notify>,
+        sink: DiscoverySink,
+        cancel: CancellationToken,
+    ) -> Result<()> {
+        let mut last_announce: Option<Instant> = None;
+        // 第一轮和手动刷新时组播 Ping 代替 Announce，其他设备收到后马上回 Pong，不用等它们的下一次广播
+        let mut refresh_requested = true;
+
+        loop {
+            // 插拔网线、连上 VPN 之后重新加入组播组，并更新公布的地址
+            let interfaces_changed = interfaces.refresh();
+            if interfaces_changed {
+                if let Ok(current) = Device::current() {
+                    device.ip = current.ip;
+                    device.addresses = current.addresses;
+                }
+            }
+
+            let due = last_announce.is_none_or(|time| time.elapsed() >= ANNOUNCE_INTERVAL);
+            if due || interfaces_changed || refresh_requested {
+                // 传输服务可能在发现启动之后才绑定到备用端口，用户也可能改了设备名
+                device.port = config::transfer_port();
+                if let Ok(name) = config::device_name() {
+                    device.name = name;
+                }
+                // 每个 Ping 的序号都在发送前一刻才分配，测出的往返时间不含主机名解析和前面目标的发送耗时
+                let ping_message = |device: &Device| DiscoveryMessage {
+                    device: device.clone(),
+                    message_type: DiscoveryMessageType::Ping,
+                    nonce: pings.lock().map(|mut pings| pings.start()).ok(),
+                };
+                let multicast_message = if refresh_requested || interfaces_changed {
+                    ping_message(&device)
+                } else {
+                    DiscoveryMessage {
+                        device: device.clone(),
+                        message_type: DiscoveryMessageType::Announce,
+                        nonce: None,
+                    }
+                };
+                
+                if let Ok(data) = serde_json::to_vec(&multicast_message) {
+                    interfaces.send(&data).await;
+                }
+
+                // 组播到不了的网段逐个单播 Ping，对方回的 Pong 和它自己收到的 Ping 都会记下设备。
+                // 已知设备也单播 Ping 一次：测出往返时间，漏掉了组播的设备也能在过期前应答
+                // 主机名解析可能要等好几秒，停止时不等它
+                let entries = Settings::load().unicast_targets;
+                let mut targets = tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    targets = unicast_targets(&entries, interfaces.port) => targets,
+                };
+                for peer in sink.known_devices().await {
+                    let target = discovery_address(&peer, interfaces.port);
+                    if !targets.contains(&target) {
+                        targets.push(target);
+                    }
+                }
+                for target in targets {
+                    if let Ok(data) = serde_json::to_vec(&ping_message(&device)) {
+                        interfaces.send_unicast(&data, target).await;
+                    }
+                }
+                last_announce = Some(Instant::now());
             }
             
-            tokio::time::sleep(Duration::from_secs(5)).await;
+            refresh_requested = tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = tokio::time::sleep(INTERFACE_POLL_INTERVAL) => false,
+                _ = refresh.notified() => true,
+            };
         }
         
         // 发送 Goodbye 消息
         let goodbye_message = DiscoveryMessage {
             device,
             message_type: DiscoveryMessageType::Goodbye,
+            nonce: None,
         };
         
         if let Ok(data) = serde_json::to_vec(&goodbye_message) {
-            let _ = socket.send_to(&data, multicast_addr);
+            interfaces.send(&data).await;
         }
+        interfaces.leave_all();
         
         Ok(())
     }
+}
 
-    async fn device_cleanup_task(
-        discovered_devices: Arc<Mutex<HashMap<String, Device>>>,
-        device_sender: broadcast::Sender<Vec<Device>>,
-        running: Arc<Mutex<bool>>,
-    ) {
-        while *running.lock().await {
-            let current_time = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-            
-            let mut devices = discovered_devices.lock().await;
-            let mut to_remove = Vec::new();
-            
-            for (id, device) in devices.iter() {
-                if current_time - device.last_seen > 30 {
-                    to_remove.push(id.clone());
+impl DiscoveryBackend for UdpBackend {
+    fn name(&self) -> &'static str {
+        "UDP"
+    }
+
+    fn start(&self, device: Device, sink: DiscoverySink, cancel: CancellationToken) -> Result<Vec<JoinHandle<()>>> {
+        // IPv4 和 IPv6 各一个监听 socket，由监听器收包，由广播器按网卡加入和退出组播组。
+        // 只有一种协议可用的网络上，绑定上一个就够了
+        let port = self.port;
+        let listener_v4 = bind_listener(Domain::IPV4, port)
+            .map_err(|e| eprintln!("Failed to bind IPv4 discovery port {}: {}", port, e))
+            .ok()
+            .map(Arc::new);
+        let listener_v6 = bind_listener(Domain::IPV6, port)
+            .map_err(|e| eprintln!("Failed to bind IPv6 discovery port {}: {}", port, e))
+            .ok()
+            .map(Arc::new);
+        if listener_v4.is_none() && listener_v6.is_none() {
+            return Err(anyhow::anyhow!("Failed to bind discovery port {}", port));
+        }
+
+        let pings = Arc::new(std::sync::Mutex::new(PendingPings::new()));
+
+        // 启动发现监听器
+        let mut tasks = Vec::new();
+        for socket in listener_v4.iter().chain(&listener_v6) {
+            let socket = socket.clone();
+            let pings = pings.clone();
+            let sink = sink.clone();
+            let cancel = cancel.clone();
+
+            tasks.push(tokio::spawn(async move {
+                if let Err(e) = Self::discovery_listener(socket, port, pings, sink, cancel).await {
+                    eprintln!("Discovery listener error: {}", e);
                 }
+            }));
+        }
+
+        // 启动广播器
+        let interfaces = MulticastInterfaces::new(listener_v4, listener_v6, port);
+        let refresh = self.refresh.clone();
+        
+        tasks.push(tokio::spawn(async move {
+            if let Err(e) = Self::discovery_broadcaster(device, interfaces, pings, refresh, sink, cancel).await {
+                eprintln!("Discovery broadcaster error: {}", e);
             }
-            
-            for id in to_remove {
-                devices.remove(&id);
+        }));
+
+        Ok(tasks)
+    }
+
+    fn refresh(&self) {
+        self.refresh.notify_one();
+    }
+}
+
+// 发出的 Ping 的序号和发送时间。组播的 Ping 会收到多台设备的 Pong，所以收到一个 Pong 后不删除，
+// 过期的记录在下次发 Ping 时清理
+struct PendingPings {
+    next: u64,
+    sent: HashMap<u64, Instant>,
+}
+
+impl PendingPings {
+    // 序号从随机值开始，同一台机器上的其他进程回来的 Pong 不会被误认
+    fn new() -> Self {
+        PendingPings {
+            next: rand::random(),
+            sent: HashMap::new(),
+        }
+    }
+
+    fn start(&mut self) -> u64 {
+        self.sent.retain(|_, sent| sent.elapsed() < PING_TIMEOUT);
+        self.next = self.next.wrapping_add(1);
+        self.sent.insert(self.next, Instant::now());
+        self.next
+    }
+
+    fn round_trip(&self, nonce: u64) -> Option<u32> {
+        let sent = self.sent.get(&nonce)?;
+        let elapsed = sent.elapsed();
+        (elapsed < PING_TIMEOUT).then_some(elapsed.as_millis() as u32)
+    }
+}
+
+// 对方的发现端口和本机相同；链路本地地址带上网卡编号
+fn discovery_address(peer: &Device, port: u16) -> SocketAddr {
+    let mut address = peer.socket_address(peer.ip);
+    address.set_port(port);
+    address
+}
+
+// 组播出口：IPv4 按网卡地址区分，IPv6 按网卡编号区分
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Interface {
+    V4(Ipv4Addr),
+    V6(u32),
+}
+
+impl std::fmt::Display for Interface {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Interface::V4(ip) => write!(f, "{}", ip),
+            Interface::V6(index) => write!(f, "IPv6 interface #{}", index),
+        }
+    }
+}
+
+// 每个网卡一个发送 socket，同时让监听 socket 在该网卡上加入组播组
+struct MulticastInterfaces {
+    listener_v4: Option<Arc<UdpSocket>>,
+    listener_v6: Option<Arc<UdpSocket>>,
+    port: u16,
+    senders: HashMap<Interface, UdpSocket>,
+}
+
+impl MulticastInterfaces {
+    fn new(listener_v4: Option<Arc<UdpSocket>>, listener_v6: Option<Arc<UdpSocket>>, port: u16) -> Self {
+        MulticastInterfaces {
+            listener_v4,
+            listener_v6,
+            port,
+            senders: HashMap::new(),
+        }
+    }
+
+    // 与当前网卡对比，新出现的加入组播组，消失的退出；返回是否有变化
+    fn refresh(&mut self) -> bool {
+        let mut current = local_interfaces();
+        current.retain(|interface| match interface {
+            Interface::V4(_) => self.listener_v4.is_some(),
+            Interface::V6(_) => self.listener_v6.is_some(),
+        });
+        let mut changed = false;
+
+        let removed: Vec<Interface> = self
+            .senders
+            .keys()
+            .filter(|interface| !current.contains(interface))
+            .copied()
+            .collect();
+        for interface in removed {
+            self.senders.remove(&interface);
+            let _ = self.leave(interface);
+            eprintln!("Discovery stopped on {}", interface);
+            changed = true;
+        }
+
+        for interface in current {
+            if self.senders.contains_key(&interface) {
+                continue;
             }
-            
-            if !devices.is_empty() {
-                let device_list: Vec<Device> = devices.values().cloned().collect();
-                let _ = device_sender.send(device_list);
+            // 同一块网卡有多个 IPv4 地址时只有第一次加入会成功，发送 socket 仍然照建
+            if let Err(e) = self.join(interface) {
+                eprintln!("Failed to join multicast group on {}: {}", interface, e);
+            }
+            match bind_sender(interface) {
+                Ok(socket) => {
+                    eprintln!("Discovery running on {}", interface);
+                    self.senders.insert(interface, socket);
+                    changed = true;
+                }
+                Err(e) => eprintln!("Failed to open discovery socket on {}: {}", interface, e),
             }
-            
-            drop(devices);
-            tokio::time::sleep(Duration::from_secs(10)).await;
         }
+
+        changed
     }
 
-    pub fn subscribe_devices(&self) -> broadcast::Receiver<Vec<Device>> {
-        self.device_sender.subscribe()
+    fn join(&self, interface: Interface) -> std::io::Result<()> {
+        match (interface, &self.listener_v4, &self.listener_v6) {
+            (Interface::V4(ip), Some(listener), _) => listener.join_multicast_v4(MULTICAST_GROUP, ip),
+            (Interface::V6(index), _, Some(listener)) => listener.join_multicast_v6(&MULTICAST_GROUP_V6, index),
+            _ => Ok(()),
+        }
+    }
+
+    fn leave(&self, interface: Interface) -> std::io::Result<()> {
+        match (interface, &self.listener_v4, &self.listener_v6) {
+            (Interface::V4(ip), Some(listener), _) => listener.leave_multicast_v4(MULTICAST_GROUP, ip),
+            (Interface::V6(index), _, Some(listener)) => listener.leave_multicast_v6(&MULTICAST_GROUP_V6, index),
+            _ => Ok(()),
+        }
     }
-} 
\ No newline at end of file
+
+    // 没有可用网卡时退回系统默认路由
+    async fn send(&self, data: &[u8]) {
+        if self.senders.is_empty() {
+            if let Some(listener) = &self.listener_v4 {
+                let _ = listener.send_to(data, SocketAddr::from((MULTICAST_GROUP, self.port))).await;
+            }
+            return;
+        }
+        for (interface, socket) in &self.senders {
+            let target = match interface {
+                Interface::V4(_) => SocketAddr::from((MULTICAST_GROUP, self.port)),
+                Interface::V6(index) => SocketAddrV6::new(MULTICAST_GROUP_V6, self.port, 0, *index).into(),
+            };
+            let _ = socket.send_to(data, target).await;
+        }
+    }
+
+    // 从监听 socket 发出，对方的 Pong 才会回到发现端口
+    async fn send_unicast(&self, data: &[u8], target: SocketAddr) {
+        let listener = match target {
+            SocketAddr::V4(_) => &self.listener_v4,
+            SocketAddr::V6(_) => &self.listener_v6,
+        };
+        if let Some(listener) = listener {
+            let _ = listener.send_to(data, target).await;
+        }
+    }
+
+    fn leave_all(&mut self) {
+        for interface in self.senders.keys() {
+            let _ = self.leave(*interface);
+        }
+        self.senders.clear();
+    }
+}
+
+// 允许端口复用，同一台机器上的多个实例（桌面端、守护进程、命令行）都能收到组播，
+// 重新启动时也不会因为旧 socket 还没关掉而绑定失败。
+// IPv6 socket 只收 IPv6，IPv4 由另一个 socket 负责
+fn bind_listener(domain: Domain, port: u16) -> Result<UdpSocket> {
+    let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    // BSD 和 macOS 上多个 socket 绑定同一个组播端口需要 SO_REUSEPORT
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    let address = if domain == Domain::IPV6 {
+        socket.set_only_v6(true)?;
+        SocketAddr::from((Ipv6Addr::UNSPECIFIED, port))
+    } else {
+        SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))
+    };
+    socket.bind(&address.into())?;
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+// 指定组播出口网卡，否则系统只会从默认路由的网卡发出
+fn bind_sender(interface: Interface) -> Result<UdpSocket> {
+    let socket = match interface {
+        Interface::V4(ip) => {
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.set_multicast_if_v4(&ip)?;
+            socket.set_multicast_loop_v4(true)?;
+            socket.bind(&SocketAddr::from((ip, 0)).into())?;
+            socket
+        }
+        Interface::V6(index) => {
+            let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.set_only_v6(true)?;
+            socket.set_multicast_if_v6(index)?;
+            socket.set_multicast_loop_v6(true)?;
+            socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)).into())?;
+            socket
+        }
+    };
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+// 有 IPv6 地址（哪怕只有链路本地地址）的网卡都能收发 IPv6 组播
+fn local_interfaces() -> Vec<Interface> {
+    let mut interfaces = Vec::new();
+    for interface in if_addrs::get_if_addrs().unwrap_or_default() {
+        if interface.is_loopback() {
+            continue;
+        }
+        let entry = match (&interface.addr, interface.index) {
+            (IfAddr::V4(addr), _) => Interface::V4(addr.ip),
+            (IfAddr::V6(_), Some(index)) => Interface::V6(index),
+            (IfAddr::V6(_), None) => continue,
+        };
+        if !interfaces.contains(&entry) {
+            interfaces.push(entry);
+        }
+    }
+    interfaces
+}
+
+// 每一项可以是 IP、主机名或 IPv4 网段；写错的项跳过，不影响其余的
+async fn unicast_targets(entries: &[String], port: u16) -> Vec<SocketAddr> {
+    let mut targets = Vec::new();
+    for entry in entries.iter().map(|entry| entry.trim()).filter(|entry| !entry.is_empty()) {
+        if let Some((network, prefix)) = entry.split_once('/') {
+            match subnet_hosts(network, prefix) {
+                Some(hosts) => targets.extend(hosts.map(|ip| SocketAddr::from((ip, port)))),
+                None => eprintln!(
+                    "Ignoring unicast target {}: expected an IPv4 subnet with at most {} hosts",
+                    entry, UNICAST_MAX_HOSTS
+                ),
+            }
+        } else if let Ok(ip) = entry.parse::<IpAddr>() {
+            targets.push(SocketAddr::new(ip, port));
+        } else {
+            match tokio::net::lookup_host((entry, port)).await {
+                Ok(addresses) => targets.extend(addresses),
+                Err(e) => eprintln!("Failed to resolve unicast target {}: {}", entry, e),
+            }
+        }
+    }
+    targets.dedup();
+    targets
+}
+
+// 不含网络地址和广播地址；/31 和 /32 没有这两个地址
+fn subnet_hosts(network: &str, prefix: &str) -> Option<impl Iterator<Item = Ipv4Addr>> {
+    let network: Ipv4Addr = network.parse().ok()?;
+    let prefix: u32 = prefix.parse().ok().filter(|prefix| *prefix <= 32)?;
+    let size = 1u64 << (32 - prefix);
+    if size > UNICAST_MAX_HOSTS as u64 {
+        return None;
+    }
+
+    let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+    let first = u32::from(network) & mask;
+    let (start, end) = if size <= 2 {
+        (first as u64, first as u64 + size)
+    } else {
+        (first as u64 + 1, first as u64 + size - 1)
+    };
+    Some((start..end).map(|ip| Ipv4Addr::from(ip as u32)))
+}
+
+fn device_list(devices: &HashMap<String, DiscoveredDevice>) -> Vec<Device> {
+    devices.values().map(|entry| entry.device.clone()).collect()
+}
+
+// 比较前后两次设备列表；last_seen 每次广播都会变，不算作变化
+pub fn diff_devices(previous: &[Device], current: &[Device]) -> DeviceDelta {
+    let previous: HashMap<&str, &Device> = previous.iter().map(|device| (device.id.as_str(), device)).collect();
+    let current_ids: HashMap<&str, &Device> = current.iter().map(|device| (device.id.as_str(), device)).collect();
+
+    let mut delta = DeviceDelta::default();
+    for device in current {
+        match previous.get(device.id.as_str()) {
+            None => delta.added.push(device.clone()),
+            Some(old) if !same_device(old, device) => delta.changed.push(device.clone()),
+            Some(_) => {}
+        }
+    }
+    for id in previous.keys() {
+        if !current_ids.contains_key(id) {
+            delta.removed.push(id.to_string());
+        }
+    }
+    delta
+}
+
+fn same_device(a: &Device, b: &Device) -> bool {
+    a.name == b.name
+        && a.ip == b.ip
+        && same_addresses(&a.addresses, &b.addresses)
+        && a.port == b.port
+        && a.device_type == b.device_type
+        && a.os == b.os
+        && a.cert_fingerprint == b.cert_fingerprint
+        && a.scope_id == b.scope_id
+        && a.presence == b.presence
+        && a.latency_ms == b.latency_ms
+}
+
+// 双栈时 IPv4 和 IPv6 收到的报文排出的地址顺序不同，只比较集合
+fn same_addresses(a: &[IpAddr], b: &[IpAddr]) -> bool {
+    a.len() == b.len() && a.iter().all(|ip| b.contains(ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts(network: &str, prefix: &str) -> Option<Vec<Ipv4Addr>> {
+        subnet_hosts(network, prefix).map(|hosts| hosts.collect())
+    }
+
+    fn device(id: &str, name: &str) -> Device {
+        Device {
+            id: id.to_string(),
+            name: name.to_string(),
+            ip: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)),
+            port: 8080,
+            device_type: "desktop".to_string(),
+            os: "linux".to_string(),
+            last_seen: 1,
+            cert_fingerprint: None,
+            addresses: Vec::new(),
+            scope_id: None,
+            presence: Presence::Online,
+            latency_ms: None,
+        }
+    }
+
+    #[test]
+    fn subnet_skips_network_and_broadcast() {
+        let hosts = hosts("10.1.2.77", "24").unwrap();
+        assert_eq!(hosts.len(), 254);
+        assert_eq!(hosts.first(), Some(&Ipv4Addr::new(10, 1, 2, 1)));
+        assert_eq!(hosts.last(), Some(&Ipv4Addr::new(10, 1, 2, 254)));
+    }
+
+    #[test]
+    fn subnet_edge_prefixes() {
+        // /31 是点对点链路，两个地址都可用；/32 就是这一台主机
+        assert_eq!(
+            hosts("10.0.0.5", "31"),
+            Some(vec![Ipv4Addr::new(10, 0, 0, 4), Ipv4Addr::new(10, 0, 0, 5)])
+        );
+        assert_eq!(hosts("10.0.0.5", "32"), Some(vec![Ipv4Addr::new(10, 0, 0, 5)]));
+        assert_eq!(hosts("10.0.0.0", "30").map(|hosts| hosts.len()), Some(2));
+    }
+
+    #[test]
+    fn subnet_rejects_invalid_or_large_ranges() {
+        assert_eq!(hosts("10.0.0.0", "33"), None);
+        assert_eq!(hosts("10.0.0.0", "0"), None);
+        assert_eq!(hosts("10.0.0.0", "21"), None);
+        assert_eq!(hosts("10.0.0.0", "x"), None);
+        assert_eq!(hosts("fe80::1", "64"), None);
+        assert_eq!(hosts("10.0.0.0", "22").map(|hosts| hosts.len()), Some(1022));
+    }
+
+    #[test]
+    fn diff_reports_added_removed_and_changed() {
+        let previous = vec![device("a", "alpha"), device("b", "beta")];
+        let current = vec![device("a", "alpha renamed"), device("c", "gamma")];
+        let delta = diff_devices(&previous, &current);
+        assert_eq!(delta.added.iter().map(|d| d.id.as_str()).collect::<Vec<_>>(), ["c"]);
+        assert_eq!(delta.removed, ["b"]);
+        assert_eq!(delta.changed.iter().map(|d| d.id.as_str()).collect::<Vec<_>>(), ["a"]);
+    }
+
+    #[test]
+    fn diff_ignores_last_seen_and_address_order() {
+        let mut before = device("a", "alpha");
+        before.addresses = vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), "fd00::1".parse().unwrap()];
+        let mut after = before.clone();
+        after.last_seen = 99;
+        after.addresses.reverse();
+        assert!(diff_devices(&[before.clone()], &[after]).is_empty());
+
+        let mut slower = before.clone();
+        slower.latency_ms = Some(40);
+        assert_eq!(diff_devices(&[before], &[slower]).changed.len(), 1);
+    }
+}
@@ -0,0 +1,24 @@
+use tokio::sync::mpsc;
+
+// 服务产生的事件都交给 EventSink：桌面端转发给前端，守护进程写进日志
+pub trait EventSink: Send + Sync + 'static {
+    fn emit(&self, event: &str, payload: serde_json::Value);
+}
+
+// 把事件转进 channel，由持有接收端的一方自行处理
+pub struct ChannelSink {
+    sender: mpsc::UnboundedSender<(String, serde_json::Value)>,
+}
+
+impl ChannelSink {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<(String, serde_json::Value)>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (ChannelSink { sender }, receiver)
+    }
+}
+
+impl EventSink for ChannelSink {
+    fn emit(&self, event: &str, payload: serde_json::Value) {
+        let _ = self.sender.send((event.to_string(), payload));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::config;
+use crate::types::{HistoryEntry, HistoryQuery};
+use anyhow::Result;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+// 文本记录只保留开头一段，避免历史文件被大段文本撑大
+const TEXT_PREVIEW_CHARS: usize = 200;
+
+// 收发记录，每条一行 JSON 追加写入配置目录的 history.jsonl
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn load() -> Self {
+        History {
+            path: config::config_dir().join("history.jsonl"),
+        }
+    }
+
+    pub fn append(&self, entry: &HistoryEntry) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        file.write_all(&line)?;
+        Ok(())
+    }
+
+    // 最新的记录排在前面；写了一半的坏行直接跳过
+    pub fn query(&self, query: &HistoryQuery) -> Result<Vec<HistoryEntry>> {
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        // 按字节切行，非 UTF-8 的坏行也只会解析失败被跳过；只有读文件本身出错才停下
+        let mut entries: Vec<HistoryEntry> = BufReader::new(file)
+            .split(b'\n')
+            .map_while(|line| line.ok())
+            .filter_map(|line| serde_json::from_slice(&line).ok())
+            .filter(|entry| matches(entry, query))
+            .collect();
+        entries.reverse();
+
+        let entries = entries.into_iter().skip(query.offset.unwrap_or(0));
+        Ok(match query.limit {
+            Some(limit) => entries.take(limit).collect(),
+            None => entries.collect(),
+        })
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+pub fn text_preview(text: &str) -> String {
+    text.chars().take(TEXT_PREVIEW_CHARS).collect()
+}
+
+fn matches(entry: &HistoryEntry, query: &HistoryQuery) -> bool {
+    if query.direction.as_ref().is_some_and(|direction| *direction != entry.direction) {
+        return false;
+    }
+    if query.kind.as_ref().is_some_and(|kind| *kind != entry.kind) {
+        return false;
+    }
+    if query.peer_id.as_ref().is_some_and(|peer_id| *peer_id != entry.peer_id) {
+        return false;
+    }
+    if query.outcome.as_ref().is_some_and(|outcome| *outcome != entry.outcome) {
+        return false;
+    }
+    if query.since.is_some_and(|since| entry.timestamp < since) {
+        return false;
+    }
+    if query.until.is_some_and(|until| entry.timestamp > until) {
+        return false;
+    }
+
+    // 关键字匹配名称、对端设备名和保存路径，不区分大小写
+    match &query.search {
+        Some(search) if !search.is_empty() => {
+            let search = search.to_lowercase();
+            entry.name.to_lowercase().contains(&search)
+                || entry.peer_name.to_lowercase().contains(&search)
+                || entry
+                    .path
+                    .as_ref()
+                    .is_some_and(|path| path.to_lowercase().contains(&search))
+        }
+        _ => true,
+    }
+}
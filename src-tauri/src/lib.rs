@@ -0,0 +1,12 @@
+pub mod config;
+pub mod discovery;
+pub mod events;
+pub mod history;
+pub mod mdns;
+pub mod pairing;
+pub mod peers;
+pub mod progress;
+pub mod sanitize;
+pub mod tls;
+pub mod transfer;
+pub mod types;
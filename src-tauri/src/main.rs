@@ -1,20 +1,28 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-mod discovery;
-mod transfer;
-mod types;
-
-use discovery::DiscoveryService;
-use transfer::TransferService;
-use types::*;
+use landrop::config::Settings;
+use landrop::discovery::{self, DiscoveryService};
+use landrop::events::EventSink;
+use landrop::transfer::TransferService;
+use landrop::types::*;
 
+use std::path::PathBuf;
 use std::sync::Arc;
-use tauri::{Manager, State};
-use tokio::sync::Mutex;
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::{broadcast, Mutex};
 
 type AppState = Arc<Mutex<AppData>>;
 
+// 服务端事件直接转发给前端
+struct TauriSink(AppHandle);
+
+impl EventSink for TauriSink {
+    fn emit(&self, event: &str, payload: serde_json::Value) {
+        let _ = self.0.emit_all(event, payload);
+    }
+}
+
 #[derive(Default)]
 struct AppData {
     discovery: Option<DiscoveryService>,
@@ -23,10 +31,11 @@ struct AppData {
 }
 
 #[tauri::command]
-async fn start_discovery(state: State<'_, AppState>) -> Result<(), String> {
+async fn start_discovery(app_handle: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
     let mut app_data = state.lock().await;
     if app_data.discovery.is_none() {
         let discovery = DiscoveryService::new().map_err(|e| e.to_string())?;
+        spawn_device_bridge(discovery.subscribe_devices(), state.inner().clone(), app_handle);
         app_data.discovery = Some(discovery);
     }
     
@@ -46,27 +55,77 @@ async fn stop_discovery(state: State<'_, AppState>) -> Result<(), String> {
     Ok(())
 }
 
+// 立即 Ping 一轮，新的设备列表照常通过 devices-updated 事件送到前端
+#[tauri::command]
+async fn refresh_devices(state: State<'_, AppState>) -> Result<(), String> {
+    let app_data = state.lock().await;
+    if let Some(discovery) = &app_data.discovery {
+        discovery.refresh_devices();
+    }
+    Ok(())
+}
+
 #[tauri::command]
 async fn get_devices(state: State<'_, AppState>) -> Result<Vec<Device>, String> {
     let app_data = state.lock().await;
     Ok(app_data.devices.clone())
 }
 
+// 把发现服务的设备列表同步到 AppData，并只把变化的部分通知前端
+fn spawn_device_bridge(
+    mut device_receiver: broadcast::Receiver<Vec<Device>>,
+    state: AppState,
+    app_handle: AppHandle,
+) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let devices = match device_receiver.recv().await {
+                Ok(devices) => devices,
+                // 落后了也没关系，下一条就是完整列表
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+
+            let mut app_data = state.lock().await;
+            let delta = discovery::diff_devices(&app_data.devices, &devices);
+            app_data.devices = devices;
+            drop(app_data);
+
+            if !delta.is_empty() {
+                let _ = app_handle.emit_all("devices-updated", delta);
+            }
+        }
+    });
+}
+
+// 取出传输服务的副本后立即释放锁，长时间的传输不会阻塞其他命令
+async fn transfer_service(state: &State<'_, AppState>) -> Result<TransferService, String> {
+    let mut app_data = state.lock().await;
+    if app_data.transfer.is_none() {
+        app_data.transfer = Some(TransferService::new().map_err(|e| e.to_string())?);
+    }
+    Ok(app_data.transfer.clone().unwrap())
+}
+
 #[tauri::command]
 async fn send_file(
     file_path: String,
     target_device: Device,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
-    let mut app_data = state.lock().await;
-    if app_data.transfer.is_none() {
-        app_data.transfer = Some(TransferService::new().map_err(|e| e.to_string())?);
-    }
-    
-    if let Some(transfer) = &app_data.transfer {
-        transfer.send_file(&file_path, &target_device).await.map_err(|e| e.to_string())?;
-    }
-    
+    let transfer = transfer_service(&state).await?;
+    transfer.send_file(&file_path, &target_device).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn send_paths(
+    paths: Vec<String>,
+    target_device: Device,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let transfer = transfer_service(&state).await?;
+    transfer.send_paths(&paths, &target_device).await.map_err(|e| e.to_string())?;
     Ok(())
 }
 
@@ -76,18 +135,157 @@ async fn send_text(
     target_device: Device,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
-    let mut app_data = state.lock().await;
-    if app_data.transfer.is_none() {
-        app_data.transfer = Some(TransferService::new().map_err(|e| e.to_string())?);
-    }
-    
-    if let Some(transfer) = &app_data.transfer {
-        transfer.send_text(&text, &target_device).await.map_err(|e| e.to_string())?;
-    }
-    
+    let transfer = transfer_service(&state).await?;
+    transfer.send_text(&text, &target_device).await.map_err(|e| e.to_string())?;
     Ok(())
 }
 
+#[tauri::command]
+async fn respond_file_request(
+    request_id: String,
+    accept: bool,
+    conflict_policy: Option<ConflictPolicy>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let transfer = transfer_service(&state).await?;
+    transfer
+        .respond_request(&request_id, accept, conflict_policy)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn pair_device(target_device: Device, state: State<'_, AppState>) -> Result<String, String> {
+    let transfer = transfer_service(&state).await?;
+    transfer.start_pairing(&target_device).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn confirm_pairing(
+    device_id: String,
+    accept: bool,
+    state: State<'_, AppState>,
+) -> Result<bool, String> {
+    let transfer = transfer_service(&state).await?;
+    transfer.confirm_pairing(&device_id, accept).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn respond_pairing(
+    pairing_id: String,
+    accept: bool,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let transfer = transfer_service(&state).await?;
+    transfer.respond_pairing(&pairing_id, accept).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_paired_devices(state: State<'_, AppState>) -> Result<Vec<PairedDevice>, String> {
+    let transfer = transfer_service(&state).await?;
+    Ok(transfer.list_paired_devices().await)
+}
+
+#[tauri::command]
+async fn unpair_device(device_id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let transfer = transfer_service(&state).await?;
+    transfer.unpair_device(&device_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_transfers(state: State<'_, AppState>) -> Result<Vec<TransferStatus>, String> {
+    let transfer = transfer_service(&state).await?;
+    Ok(transfer.list_transfers().await)
+}
+
+#[tauri::command]
+async fn get_settings(state: State<'_, AppState>) -> Result<Settings, String> {
+    let transfer = transfer_service(&state).await?;
+    Ok(transfer.settings().await)
+}
+
+#[tauri::command]
+async fn update_settings(settings: Settings, state: State<'_, AppState>) -> Result<(), String> {
+    let transfer = transfer_service(&state).await?;
+    transfer.update_settings(settings).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_device_name(name: Option<String>, state: State<'_, AppState>) -> Result<Device, String> {
+    let transfer = transfer_service(&state).await?;
+    transfer.set_device_name(name).await.map_err(|e| e.to_string())?;
+    Device::current().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_receive_dir(state: State<'_, AppState>) -> Result<String, String> {
+    let transfer = transfer_service(&state).await?;
+    Ok(transfer.receive_dir().await.to_string_lossy().to_string())
+}
+
+#[tauri::command]
+async fn set_receive_dir(path: Option<String>, state: State<'_, AppState>) -> Result<(), String> {
+    let transfer = transfer_service(&state).await?;
+    transfer
+        .set_receive_dir(path.map(PathBuf::from))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_routing_rules(state: State<'_, AppState>) -> Result<Vec<RoutingRule>, String> {
+    let transfer = transfer_service(&state).await?;
+    Ok(transfer.list_routing_rules().await)
+}
+
+#[tauri::command]
+async fn add_routing_rule(rule: RoutingRule, state: State<'_, AppState>) -> Result<RoutingRule, String> {
+    let transfer = transfer_service(&state).await?;
+    transfer.add_routing_rule(rule).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn remove_routing_rule(rule_id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let transfer = transfer_service(&state).await?;
+    transfer.remove_routing_rule(&rule_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_static_peers(state: State<'_, AppState>) -> Result<Vec<StaticPeer>, String> {
+    let transfer = transfer_service(&state).await?;
+    Ok(transfer.list_static_peers().await)
+}
+
+#[tauri::command]
+async fn add_static_peer(host: String, port: u16, state: State<'_, AppState>) -> Result<Device, String> {
+    let transfer = transfer_service(&state).await?;
+    transfer.add_static_peer(&host, port).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn remove_static_peer(host: String, port: u16, state: State<'_, AppState>) -> Result<(), String> {
+    let transfer = transfer_service(&state).await?;
+    transfer.remove_static_peer(&host, port).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn query_history(
+    query: Option<HistoryQuery>,
+    state: State<'_, AppState>,
+) -> Result<Vec<HistoryEntry>, String> {
+    let transfer = transfer_service(&state).await?;
+    transfer
+        .query_history(&query.unwrap_or_default())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn clear_history(state: State<'_, AppState>) -> Result<(), String> {
+    let transfer = transfer_service(&state).await?;
+    transfer.clear_history().await.map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn get_device_info() -> Result<Device, String> {
     let device = Device::current().map_err(|e| e.to_string())?;
@@ -100,9 +298,31 @@ fn main() {
         .invoke_handler(tauri::generate_handler![
             start_discovery,
             stop_discovery,
+            refresh_devices,
             get_devices,
             send_file,
+            send_paths,
             send_text,
+            respond_file_request,
+            pair_device,
+            confirm_pairing,
+            respond_pairing,
+            list_paired_devices,
+            unpair_device,
+            list_transfers,
+            get_settings,
+            update_settings,
+            set_device_name,
+            get_receive_dir,
+            set_receive_dir,
+            list_routing_rules,
+            add_routing_rule,
+            remove_routing_rule,
+            list_static_peers,
+            add_static_peer,
+            remove_static_peer,
+            query_history,
+            clear_history,
             get_device_info
         ])
         .setup(|app| {
@@ -112,10 +332,28 @@ fn main() {
             tauri::async_runtime::spawn(async move {
                 let state: State<AppState> = app_handle.state();
                 let mut app_data = state.lock().await;
-                if let Ok(transfer_service) = TransferService::new() {
-                    if let Err(e) = transfer_service.start_server(app_handle.clone()).await {
+                if let Ok(mut transfer_service) = TransferService::new() {
+                    let events = Arc::new(TauriSink(app_handle.clone()));
+                    if let Err(e) = transfer_service.start_server(events).await {
                         eprintln!("Failed to start transfer server: {}", e);
+                        let _ = app_handle.emit_all("server-error", e.to_string());
                     }
+
+                    // 把收发两端的进度转发给前端
+                    let mut progress_receiver = transfer_service.subscribe_progress();
+                    let progress_handle = app_handle.clone();
+                    tauri::async_runtime::spawn(async move {
+                        loop {
+                            match progress_receiver.recv().await {
+                                Ok(progress) => {
+                                    let _ = progress_handle.emit_all("transfer-progress", progress);
+                                }
+                                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                                Err(broadcast::error::RecvError::Closed) => break,
+                            }
+                        }
+                    });
+
                     app_data.transfer = Some(transfer_service);
                 }
             });
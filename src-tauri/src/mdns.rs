@@ -0,0 +1,186 @@
+use crate::config;
+use crate::discovery::{DiscoveryBackend, DiscoverySink};
+use crate::types::{self, Device, Presence};
+use anyhow::Result;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+// 可以用 avahi-browse -r _landrop._tcp 或 dns-sd -B _landrop._tcp 查看
+const SERVICE_TYPE: &str = "_landrop._tcp.local.";
+
+// mDNS 只在记录变化或过期时通知，而服务记录的 TTL 长达 75 分钟，对方掉线后很久都不会过期。
+// 所以定期让 mdns-sd 重新确认已解析的设备：超时前对方应答了才算仍然在线，
+// 没有应答时 mdns-sd 清掉记录并发出 ServiceRemoved
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(3);
+
+// DNS 标签最长 63 字节，实例名里还要留出 id 前缀的位置
+const INSTANCE_NAME_MAX_BYTES: usize = 48;
+
+// 通过 mDNS/DNS-SD 发布本机并浏览 _landrop._tcp 服务，能穿过只放行 mDNS 的交换机
+pub struct MdnsBackend;
+
+impl DiscoveryBackend for MdnsBackend {
+    fn name(&self) -> &'static str {
+        "mDNS"
+    }
+
+    fn start(&self, device: Device, sink: DiscoverySink, cancel: CancellationToken) -> Result<Vec<JoinHandle<()>>> {
+        let daemon = ServiceDaemon::new()?;
+        let receiver = daemon.browse(SERVICE_TYPE)?;
+        let registered = register(&daemon, &device)?;
+
+        let task = tokio::spawn(async move {
+            if let Err(e) = run(daemon, receiver, registered, device, sink, cancel).await {
+                eprintln!("mDNS discovery error: {}", e);
+            }
+        });
+
+        Ok(vec![task])
+    }
+}
+
+async fn run(
+    daemon: ServiceDaemon,
+    receiver: mdns_sd::Receiver<ServiceEvent>,
+    mut registered: String,
+    mut device: Device,
+    sink: DiscoverySink,
+    cancel: CancellationToken,
+) -> Result<()> {
+    // 服务全名 -> 解析出的设备
+    let mut resolved: HashMap<String, Device> = HashMap::new();
+    // 正在确认的服务全名 -> 确认截止时间
+    let mut verifying: HashMap<String, Instant> = HashMap::new();
+    let mut refresh = tokio::time::interval_at(tokio::time::Instant::now() + REFRESH_INTERVAL, REFRESH_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            event = receiver.recv_async() => match event {
+                Ok(ServiceEvent::ServiceResolved(info)) => {
+                    if let Some(peer) = device_from_service(&info) {
+                        if !sink.is_self(&peer.id) {
+                            sink.device_seen(peer.clone(), None).await;
+                            resolved.insert(info.get_fullname().to_string(), peer);
+                        }
+                    }
+                }
+                Ok(ServiceEvent::ServiceRemoved(_, fullname)) => {
+                    verifying.remove(&fullname);
+                    if let Some(peer) = resolved.remove(&fullname) {
+                        sink.device_left(&peer.id).await;
+                    }
+                }
+                Ok(_) => {}
+                // 守护线程意外退出
+                Err(_) => return Err(anyhow::anyhow!("mDNS daemon stopped")),
+            },
+            _ = refresh.tick() => {
+                // 上一轮确认已经过了截止时间，记录还在，说明对方应答了
+                let now = Instant::now();
+                for (fullname, peer) in &resolved {
+                    if verifying.get(fullname).is_some_and(|deadline| *deadline <= now) {
+                        sink.device_seen(peer.clone(), None).await;
+                    }
+                }
+                verifying.clear();
+                for fullname in resolved.keys() {
+                    if daemon.verify(fullname.clone(), VERIFY_TIMEOUT).is_ok() {
+                        verifying.insert(fullname.clone(), now + VERIFY_TIMEOUT);
+                    }
+                }
+
+                // 传输服务可能换到了备用端口，用户也可能改了设备名，这两项都写在服务记录里，需要重新发布
+                let port = config::transfer_port();
+                let name = config::device_name().unwrap_or_else(|_| device.name.clone());
+                if port != device.port || name != device.name {
+                    let _ = daemon.unregister(&registered);
+                    device.port = port;
+                    device.name = name;
+                    registered = register(&daemon, &device)?;
+                }
+            }
+        }
+    }
+
+    // 先注销发出 goodbye，再关闭守护线程
+    let _ = daemon.unregister(&registered);
+    let _ = daemon.shutdown();
+    Ok(())
+}
+
+// 返回服务全名，注销时要用
+fn register(daemon: &ServiceDaemon, device: &Device) -> Result<String> {
+    let mut properties = HashMap::new();
+    properties.insert("id".to_string(), device.id.clone());
+    properties.insert("name".to_string(), device.name.clone());
+    properties.insert("type".to_string(), device.device_type.clone());
+    properties.insert("os".to_string(), device.os.clone());
+    if let Some(fingerprint) = &device.cert_fingerprint {
+        properties.insert("fp".to_string(), fingerprint.clone());
+    }
+
+    // 地址留空并交给 mdns-sd 按网卡自动填写，网卡变化时也会跟着更新
+    let info = ServiceInfo::new(
+        SERVICE_TYPE,
+        &instance_name(device),
+        &format!("{}.local.", device.id),
+        "",
+        device.port,
+        properties,
+    )?
+    .enable_addr_auto();
+    let fullname = info.get_fullname().to_string();
+    daemon.register(info)?;
+    Ok(fullname)
+}
+
+// 实例名就是 avahi-browse 里看到的名字；同名设备很常见，加上 id 前缀避免冲突。
+// 点号在 DNS 名字里是分隔符，换成空格
+fn instance_name(device: &Device) -> String {
+    let mut name = String::new();
+    for c in device.name.chars().map(|c| if c == '.' { ' ' } else { c }) {
+        if name.len() + c.len_utf8() > INSTANCE_NAME_MAX_BYTES {
+            break;
+        }
+        name.push(c);
+    }
+    let short_id: String = device.id.chars().take(8).collect();
+    format!("{} ({})", name.trim(), short_id)
+}
+
+// 不是 LanDrop 发布的同类型服务缺少 id，直接忽略
+fn device_from_service(info: &ServiceInfo) -> Option<Device> {
+    let id = info.get_property_val_str("id")?.to_string();
+
+    // 链路本地地址不知道该走哪块网卡，mDNS 记录里的先不用；IPv4 排在前面
+    let mut addresses: Vec<IpAddr> = info
+        .get_addresses()
+        .iter()
+        .copied()
+        .filter(|ip| !ip.is_loopback() && !types::is_link_local(ip))
+        .collect();
+    addresses.sort_by_key(|ip| (ip.is_ipv6(), *ip));
+    let ip = *addresses.first()?;
+
+    let property = |key: &str| info.get_property_val_str(key).map(str::to_string);
+    Some(Device {
+        name: property("name").unwrap_or_else(|| id.clone()),
+        id,
+        ip,
+        port: info.get_port(),
+        device_type: property("type").unwrap_or_else(|| "desktop".to_string()),
+        os: property("os").unwrap_or_default(),
+        last_seen: 0,
+        cert_fingerprint: property("fp"),
+        addresses,
+        scope_id: None,
+        presence: Presence::Online,
+        latency_ms: None,
+    })
+}
@@ -0,0 +1,348 @@
+use crate::config;
+use crate::types::PairedDevice;
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const HEADER_DEVICE: &str = "x-landrop-device";
+pub const HEADER_TIMESTAMP: &str = "x-landrop-timestamp";
+pub const HEADER_NONCE: &str = "x-landrop-nonce";
+pub const HEADER_SIGNATURE: &str = "x-landrop-signature";
+
+// 请求时间戳与本机时间相差超过这个值就拒绝，防止重放
+pub const MAX_CLOCK_SKEW_SECS: u64 = 300;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TrustEntry {
+    id: String,
+    name: String,
+    key: String,
+    #[serde(default)]
+    cert_fingerprint: Option<String>,
+    paired_at: u64,
+}
+
+// 已配对设备及其长期密钥，保存在配置目录的 trusted_devices.json
+pub struct TrustStore {
+    path: PathBuf,
+    entries: HashMap<String, TrustEntry>,
+}
+
+impl TrustStore {
+    pub fn load() -> Self {
+        let path = config::config_dir().join("trusted_devices.json");
+        let entries = read_entries(&path);
+        TrustStore { path, entries }
+    }
+
+    // 同一配置目录可能被守护进程和命令行同时使用，先合并磁盘上的最新内容
+    fn reload(&mut self) {
+        self.entries = read_entries(&self.path);
+    }
+
+    // 里面是各设备的长期密钥，不能让其他用户读到
+    fn save(&self) -> Result<()> {
+        let entries: Vec<&TrustEntry> = self.entries.values().collect();
+        config::write_private(&self.path, &serde_json::to_vec_pretty(&entries)?)
+    }
+
+    // 查不到时重新读一次文件，其他进程刚完成的配对不用重启就能生效
+    pub fn key_for(&mut self, device_id: &str) -> Option<Vec<u8>> {
+        if !self.entries.contains_key(device_id) {
+            self.reload();
+        }
+        self.entries
+            .get(device_id)
+            .and_then(|entry| hex::decode(&entry.key).ok())
+    }
+
+    pub fn fingerprint_for(&self, device_id: &str) -> Option<String> {
+        self.entries
+            .get(device_id)
+            .and_then(|entry| entry.cert_fingerprint.clone())
+    }
+
+    pub fn insert(&mut self, device_id: &str, name: &str, key: &[u8], cert_fingerprint: Option<String>) -> Result<()> {
+        self.reload();
+        self.entries.insert(
+            device_id.to_string(),
+            TrustEntry {
+                id: device_id.to_string(),
+                name: name.to_string(),
+                key: hex::encode(key),
+                cert_fingerprint,
+                paired_at: now_secs(),
+            },
+        );
+        self.save()
+    }
+
+    pub fn remove(&mut self, device_id: &str) -> Result<()> {
+        self.reload();
+        self.entries.remove(device_id);
+        self.save()
+    }
+
+    pub fn list(&mut self) -> Vec<PairedDevice> {
+        self.reload();
+        self.entries
+            .values()
+            .map(|entry| PairedDevice {
+                id: entry.id.clone(),
+                name: entry.name.clone(),
+                paired_at: entry.paired_at,
+            })
+            .collect()
+    }
+}
+
+fn read_entries(path: &Path) -> HashMap<String, TrustEntry> {
+    std::fs::read(path)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<Vec<TrustEntry>>(&data).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|entry| (entry.id.clone(), entry))
+        .collect()
+}
+
+// 一次配对用的临时密钥对
+pub struct KeyExchange {
+    secret: EphemeralSecret,
+    public: PublicKey,
+}
+
+impl KeyExchange {
+    pub fn generate() -> Self {
+        let secret = EphemeralSecret::random_from_rng(rand::rngs::OsRng);
+        let public = PublicKey::from(&secret);
+        KeyExchange { secret, public }
+    }
+
+    pub fn public_key(&self) -> String {
+        hex::encode(self.public.as_bytes())
+    }
+
+    // 返回 (长期密钥, 双方屏幕上显示的 6 位确认码)；initiator 决定两个公钥的拼接顺序。
+    // 双方的证书指纹也参与计算，中间人替换证书会导致两边确认码不一致
+    pub fn finish(
+        self,
+        peer_public_key: &str,
+        initiator: bool,
+        initiator_fingerprint: &str,
+        responder_fingerprint: &str,
+    ) -> Result<(Vec<u8>, String)> {
+        let peer_bytes: [u8; 32] = hex::decode(peer_public_key)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Invalid public key length"))?;
+        let peer_public = PublicKey::from(peer_bytes);
+        let shared = self.secret.diffie_hellman(&peer_public);
+
+        let (first, second) = if initiator {
+            (self.public, peer_public)
+        } else {
+            (peer_public, self.public)
+        };
+
+        let mut key_hasher = Sha256::new();
+        key_hasher.update(b"landrop-pairing-key");
+        key_hasher.update(shared.as_bytes());
+        key_hasher.update(first.as_bytes());
+        key_hasher.update(second.as_bytes());
+        key_hasher.update(initiator_fingerprint.as_bytes());
+        key_hasher.update(responder_fingerprint.as_bytes());
+        let key = key_hasher.finalize().to_vec();
+
+        let mut code_hasher = Sha256::new();
+        code_hasher.update(b"landrop-pairing-code");
+        code_hasher.update(shared.as_bytes());
+        code_hasher.update(first.as_bytes());
+        code_hasher.update(second.as_bytes());
+        code_hasher.update(initiator_fingerprint.as_bytes());
+        code_hasher.update(responder_fingerprint.as_bytes());
+        let digest = code_hasher.finalize();
+        let code = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) % 1_000_000;
+
+        Ok((key, format!("{:06}", code)))
+    }
+}
+
+// 发起方用来证明自己确实持有协商出的密钥
+pub fn confirmation_proof(key: &[u8], device_id: &str) -> String {
+    sign(key, &format!("pair-confirm\n{}", device_id))
+}
+
+pub fn verify_confirmation(key: &[u8], device_id: &str, proof: &str) -> bool {
+    verify(key, &format!("pair-confirm\n{}", device_id), proof)
+}
+
+pub fn auth_headers(key: &[u8], device_id: &str, method: &str, path: &str) -> Vec<(&'static str, String)> {
+    let timestamp = now_secs().to_string();
+    let mut nonce = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let nonce = hex::encode(nonce);
+    let signature = sign(key, &signing_payload(method, path, device_id, &timestamp, &nonce));
+
+    vec![
+        (HEADER_DEVICE, device_id.to_string()),
+        (HEADER_TIMESTAMP, timestamp),
+        (HEADER_NONCE, nonce),
+        (HEADER_SIGNATURE, signature),
+    ]
+}
+
+pub fn verify_request(
+    key: &[u8],
+    method: &str,
+    path: &str,
+    device_id: &str,
+    timestamp: &str,
+    nonce: &str,
+    signature: &str,
+) -> bool {
+    verify(key, &signing_payload(method, path, device_id, timestamp, nonce), signature)
+}
+
+fn verify(key: &[u8], payload: &str, signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let Ok(mut mac) = HmacSha256::new_from_slice(key) else {
+        return false;
+    };
+    mac.update(payload.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn sign(key: &[u8], payload: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn signing_payload(method: &str, path: &str, device_id: &str, timestamp: &str, nonce: &str) -> String {
+    format!("{}\n{}\n{}\n{}\n{}", method, path, device_id, timestamp, nonce)
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 双方各自完成密钥交换，返回 (发起方结果, 接收方结果)；指纹是各自认为的双方证书指纹
+    fn exchange(
+        initiator_view: (&str, &str),
+        responder_view: (&str, &str),
+    ) -> ((Vec<u8>, String), (Vec<u8>, String)) {
+        let initiator = KeyExchange::generate();
+        let responder = KeyExchange::generate();
+        let initiator_public = initiator.public_key();
+        let responder_public = responder.public_key();
+        (
+            initiator
+                .finish(&responder_public, true, initiator_view.0, initiator_view.1)
+                .unwrap(),
+            responder
+                .finish(&initiator_public, false, responder_view.0, responder_view.1)
+                .unwrap(),
+        )
+    }
+
+    fn signed_headers(key: &[u8], method: &str, path: &str) -> HashMap<&'static str, String> {
+        auth_headers(key, "device-a", method, path).into_iter().collect()
+    }
+
+    #[test]
+    fn both_sides_derive_the_same_key_and_code() {
+        let ((initiator_key, initiator_code), (responder_key, responder_code)) =
+            exchange(("fp-initiator", "fp-responder"), ("fp-initiator", "fp-responder"));
+        assert_eq!(initiator_key, responder_key);
+        assert_eq!(initiator_code, responder_code);
+        assert_eq!(initiator_code.len(), 6);
+        assert!(initiator_code.chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn substituted_certificate_changes_key_and_code() {
+        // 中间人换了证书，接收方看到的发起方指纹与发起方自己的不同
+        let ((initiator_key, initiator_code), (responder_key, responder_code)) =
+            exchange(("fp-initiator", "fp-responder"), ("fp-attacker", "fp-responder"));
+        assert_ne!(initiator_key, responder_key);
+        assert_ne!(initiator_code, responder_code);
+    }
+
+    #[test]
+    fn rejects_malformed_public_key() {
+        assert!(KeyExchange::generate().finish("zz", true, "", "").is_err());
+        assert!(KeyExchange::generate().finish("abcd", true, "", "").is_err());
+    }
+
+    #[test]
+    fn accepts_a_correctly_signed_request() {
+        let key = b"shared-key";
+        let headers = signed_headers(key, "POST", "/api/receive/file");
+        assert!(verify_request(
+            key,
+            "POST",
+            "/api/receive/file",
+            &headers[HEADER_DEVICE],
+            &headers[HEADER_TIMESTAMP],
+            &headers[HEADER_NONCE],
+            &headers[HEADER_SIGNATURE],
+        ));
+    }
+
+    #[test]
+    fn rejects_tampered_requests() {
+        let key = b"shared-key";
+        let headers = signed_headers(key, "POST", "/api/receive/file");
+        let verify_with = |key: &[u8], method: &str, path: &str, device: &str, signature: &str| {
+            verify_request(
+                key,
+                method,
+                path,
+                device,
+                &headers[HEADER_TIMESTAMP],
+                &headers[HEADER_NONCE],
+                signature,
+            )
+        };
+        let signature = &headers[HEADER_SIGNATURE];
+
+        assert!(!verify_with(key, "POST", "/api/receive/text", "device-a", signature));
+        assert!(!verify_with(key, "GET", "/api/receive/file", "device-a", signature));
+        assert!(!verify_with(key, "POST", "/api/receive/file", "device-b", signature));
+        assert!(!verify_with(b"other-key", "POST", "/api/receive/file", "device-a", signature));
+
+        let mut tampered = signature.clone();
+        let last = if tampered.ends_with('0') { "1" } else { "0" };
+        tampered.replace_range(tampered.len() - 1.., last);
+        assert!(!verify_with(key, "POST", "/api/receive/file", "device-a", &tampered));
+        assert!(!verify_with(key, "POST", "/api/receive/file", "device-a", "not-hex"));
+        assert!(!verify_with(key, "POST", "/api/receive/file", "device-a", ""));
+    }
+
+    #[test]
+    fn verifies_confirmation_proof() {
+        let key = b"shared-key";
+        let proof = confirmation_proof(key, "device-a");
+        assert!(verify_confirmation(key, "device-a", &proof));
+        assert!(!verify_confirmation(key, "device-b", &proof));
+        assert!(!verify_confirmation(b"other-key", "device-a", &proof));
+        assert!(!verify_confirmation(key, "device-a", "not-hex"));
+    }
+}
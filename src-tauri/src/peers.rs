@@ -0,0 +1,115 @@
+use crate::config::Settings;
+use crate::discovery::{DiscoveryBackend, DiscoverySink};
+use crate::tls;
+use crate::types::Device;
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+// 定期探测手动添加的设备；设置每轮重新读取，新加的设备下一轮就会出现
+#[derive(Default)]
+pub struct StaticPeerBackend {
+    refresh: Arc<Notify>,
+}
+
+impl DiscoveryBackend for StaticPeerBackend {
+    fn name(&self) -> &'static str {
+        "Static peer"
+    }
+
+    fn start(&self, _device: Device, sink: DiscoverySink, cancel: CancellationToken) -> Result<Vec<JoinHandle<()>>> {
+        let refresh = self.refresh.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                let settings = Settings::load();
+                let probes = settings
+                    .static_peers
+                    .iter()
+                    .map(|peer| probe(&peer.host, peer.port, settings.allow_plaintext));
+                // 连不上的设备要等到超时，停止时不等它们
+                let results = tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    results = futures::future::join_all(probes) => results,
+                };
+                for (device, address) in results.into_iter().flatten() {
+                    sink.device_seen(device, Some(address)).await;
+                }
+
+                tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    _ = tokio::time::sleep(PROBE_INTERVAL) => {}
+                    _ = refresh.notified() => {}
+                }
+            }
+        });
+
+        Ok(vec![task])
+    }
+
+    fn refresh(&self) {
+        self.refresh.notify_one();
+    }
+}
+
+// 主机名可能解析出多个地址，返回第一个连通地址上的设备信息
+pub async fn probe(host: &str, port: u16, allow_plaintext: bool) -> Result<(Device, SocketAddr)> {
+    let addresses = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to resolve {}: {}", host, e))?;
+
+    let mut last_error = anyhow::anyhow!("{} has no address", host);
+    for address in addresses {
+        match probe_address(address, allow_plaintext).await {
+            Ok(device) => return Ok((device, address)),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+// 先从 /api/device 读出对方公布的证书指纹，再用固定了这个指纹的连接请求 /api/ping，
+// 确认证书确实属于对方；之后的传输都按这个指纹校验
+async fn probe_address(address: SocketAddr, allow_plaintext: bool) -> Result<Device> {
+    let unverified = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .timeout(PROBE_TIMEOUT)
+        .build()?;
+    let device = match get_device(&unverified, &format!("https://{}/api/device", address)).await {
+        Ok(device) => device,
+        Err(_) if allow_plaintext => {
+            let client = reqwest::Client::builder().timeout(PROBE_TIMEOUT).build()?;
+            let device = get_device(&client, &format!("http://{}/api/device", address)).await?;
+            client
+                .get(format!("http://{}/api/ping", address))
+                .send()
+                .await?
+                .error_for_status()?;
+            return Ok(device);
+        }
+        Err(e) => return Err(e),
+    };
+
+    let fingerprint = device
+        .cert_fingerprint
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("{} did not publish a certificate fingerprint", address))?;
+    let client = tls::pinned_client(&fingerprint, reqwest::Client::builder().timeout(PROBE_TIMEOUT))?;
+    client
+        .get(format!("https://{}/api/ping", address))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(device)
+}
+
+async fn get_device(client: &reqwest::Client, url: &str) -> Result<Device> {
+    Ok(client.get(url).send().await?.error_for_status()?.json().await?)
+}
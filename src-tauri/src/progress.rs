@@ -0,0 +1,131 @@
+use crate::types::{TransferProgress, TransferStatus};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, Mutex};
+
+// 进度事件最多每 250ms 发一次，避免大文件把前端事件队列塞满
+const REPORT_INTERVAL: Duration = Duration::from_millis(250);
+
+// 结束的传输在列表里保留 10 分钟，让界面来得及显示结果
+const FINISHED_RETENTION: Duration = Duration::from_secs(600);
+
+pub fn prune_finished(transfers: &mut HashMap<String, TransferStatus>) {
+    transfers.retain(|_, status| status.finished_at.is_none_or(|finished| finished.elapsed() < FINISHED_RETENTION));
+}
+
+pub struct ProgressTracker {
+    transfer_id: String,
+    bytes_transferred: u64,
+    total_bytes: u64,
+    // 续传时从 offset 开始计时，吞吐量只统计本次传输的字节
+    start_bytes: u64,
+    started: Instant,
+    last_report: Instant,
+    transfers: Arc<Mutex<HashMap<String, TransferStatus>>>,
+    progress_sender: broadcast::Sender<TransferProgress>,
+}
+
+impl ProgressTracker {
+    pub async fn start(
+        transfers: Arc<Mutex<HashMap<String, TransferStatus>>>,
+        progress_sender: broadcast::Sender<TransferProgress>,
+        transfer_id: &str,
+        file_name: &str,
+        direction: &str,
+        offset: u64,
+        total_bytes: u64,
+    ) -> Self {
+        let mut statuses = transfers.lock().await;
+        prune_finished(&mut statuses);
+        statuses.insert(
+            transfer_id.to_string(),
+            TransferStatus {
+                transfer_id: transfer_id.to_string(),
+                file_name: file_name.to_string(),
+                direction: direction.to_string(),
+                status: "transferring".to_string(),
+                bytes_transferred: offset,
+                total_bytes,
+                error: None,
+                finished_at: None,
+            },
+        );
+        drop(statuses);
+
+        let now = Instant::now();
+        let tracker = ProgressTracker {
+            transfer_id: transfer_id.to_string(),
+            bytes_transferred: offset,
+            total_bytes,
+            start_bytes: offset,
+            started: now,
+            last_report: now,
+            transfers,
+            progress_sender,
+        };
+        let _ = tracker.progress_sender.send(tracker.snapshot());
+        tracker
+    }
+
+    pub async fn advance(&mut self, bytes: u64) {
+        self.bytes_transferred += bytes;
+        if self.last_report.elapsed() >= REPORT_INTERVAL {
+            self.last_report = Instant::now();
+            self.report().await;
+        }
+    }
+
+    pub async fn finish(&self, result: &anyhow::Result<()>) {
+        let (status, error) = match result {
+            Ok(()) => ("completed", None),
+            Err(e) => ("failed", Some(e.to_string())),
+        };
+        self.set_status(status, error).await;
+    }
+
+    pub async fn set_status(&self, status: &str, error: Option<String>) {
+        if let Some(entry) = self.transfers.lock().await.get_mut(&self.transfer_id) {
+            entry.status = status.to_string();
+            entry.bytes_transferred = self.bytes_transferred;
+            entry.error = error;
+            entry.finished_at = (status != "transferring").then(Instant::now);
+        }
+        let _ = self.progress_sender.send(self.snapshot());
+    }
+
+    async fn report(&self) {
+        if let Some(entry) = self.transfers.lock().await.get_mut(&self.transfer_id) {
+            entry.bytes_transferred = self.bytes_transferred;
+        }
+        let _ = self.progress_sender.send(self.snapshot());
+    }
+
+    fn snapshot(&self) -> TransferProgress {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let bytes_per_second = if elapsed > 0.0 {
+            (self.bytes_transferred - self.start_bytes) as f64 / elapsed
+        } else {
+            0.0
+        };
+        let remaining = self.total_bytes.saturating_sub(self.bytes_transferred);
+        let eta_seconds = if bytes_per_second > 0.0 {
+            Some((remaining as f64 / bytes_per_second).ceil() as u64)
+        } else {
+            None
+        };
+
+        TransferProgress {
+            transfer_id: self.transfer_id.clone(),
+            bytes_transferred: self.bytes_transferred,
+            total_bytes: self.total_bytes,
+            percentage: if self.total_bytes > 0 {
+                (self.bytes_transferred as f64 / self.total_bytes as f64 * 100.0) as f32
+            } else {
+                100.0
+            },
+            bytes_per_second,
+            eta_seconds,
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::path::PathBuf;
+
+// Windows 上的保留设备名，带任意扩展名也不能用
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+const MAX_COMPONENT_BYTES: usize = 255;
+
+// 把发送方给出的相对路径变成可以安全拼到接收目录下的路径。
+// 绝对路径和包含 .. 的路径直接拒绝，其余每一级都做文件名清洗
+pub fn sanitize_relative_path(name: &str) -> Option<PathBuf> {
+    if is_absolute(name) {
+        return None;
+    }
+
+    let mut path = PathBuf::new();
+    for component in name.split(['/', '\\']) {
+        if component == ".." {
+            return None;
+        }
+        if let Some(component) = sanitize_file_name(component) {
+            path.push(component);
+        }
+    }
+
+    if path.as_os_str().is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+// 清洗单个文件名：去掉控制字符，替换各平台不允许的字符，避开保留名
+pub fn sanitize_file_name(name: &str) -> Option<String> {
+    let cleaned: String = name
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| match c {
+            '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c => c,
+        })
+        .collect();
+
+    // Windows 会悄悄去掉结尾的点和空格
+    let cleaned = cleaned
+        .trim_start()
+        .trim_end_matches(|c: char| c == '.' || c.is_whitespace())
+        .to_string();
+    if cleaned.is_empty() {
+        return None;
+    }
+
+    let stem = cleaned.split('.').next().unwrap_or_default().to_ascii_uppercase();
+    let cleaned = if RESERVED_NAMES.contains(&stem.trim_end()) {
+        format!("_{}", cleaned)
+    } else {
+        cleaned
+    };
+
+    Some(truncate_to_bytes(cleaned, MAX_COMPONENT_BYTES))
+}
+
+fn is_absolute(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    name.starts_with('/')
+        || name.starts_with('\\')
+        || (bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':')
+}
+
+// 按字节截断，但不切断 UTF-8 字符，尽量保留扩展名
+fn truncate_to_bytes(name: String, max: usize) -> String {
+    if name.len() <= max {
+        return name;
+    }
+
+    let extension = name
+        .rfind('.')
+        .filter(|&index| index > 0 && name.len() - index <= 16)
+        .map(|index| name[index..].to_string())
+        .unwrap_or_default();
+
+    let mut end = max - extension.len();
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &name[..end], extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_ordinary_names() {
+        assert_eq!(sanitize_relative_path("report.pdf"), Some(PathBuf::from("report.pdf")));
+        assert_eq!(sanitize_relative_path("照片 2024.jpg"), Some(PathBuf::from("照片 2024.jpg")));
+        assert_eq!(
+            sanitize_relative_path("project/src/main.rs"),
+            Some(PathBuf::from("project").join("src").join("main.rs"))
+        );
+    }
+
+    #[test]
+    fn rejects_parent_traversal() {
+        assert_eq!(sanitize_relative_path("../../.bashrc"), None);
+        assert_eq!(sanitize_relative_path("docs/../../etc/passwd"), None);
+        assert_eq!(sanitize_relative_path("..\\..\\Windows\\System32\\evil.dll"), None);
+        assert_eq!(sanitize_relative_path(".."), None);
+    }
+
+    #[test]
+    fn rejects_absolute_paths() {
+        assert_eq!(sanitize_relative_path("/etc/passwd"), None);
+        assert_eq!(sanitize_relative_path("\\Windows\\win.ini"), None);
+        assert_eq!(sanitize_relative_path("C:\\Users\\me\\evil.exe"), None);
+        assert_eq!(sanitize_relative_path("c:evil.exe"), None);
+        assert_eq!(sanitize_relative_path("\\\\server\\share\\file"), None);
+    }
+
+    #[test]
+    fn drops_empty_and_current_dir_components() {
+        assert_eq!(sanitize_relative_path("a//./b"), Some(PathBuf::from("a").join("b")));
+        assert_eq!(sanitize_relative_path("./"), None);
+        assert_eq!(sanitize_relative_path(""), None);
+    }
+
+    #[test]
+    fn strips_control_and_reserved_characters() {
+        assert_eq!(sanitize_file_name("evil\u{0}name\n.txt"), Some("evilname.txt".to_string()));
+        assert_eq!(sanitize_file_name("a<b>c:d\"e|f?g*h.txt"), Some("a_b_c_d_e_f_g_h.txt".to_string()));
+        assert_eq!(sanitize_file_name("\u{1b}[31mred"), Some("[31mred".to_string()));
+    }
+
+    #[test]
+    fn escapes_windows_reserved_names() {
+        assert_eq!(sanitize_file_name("CON"), Some("_CON".to_string()));
+        assert_eq!(sanitize_file_name("nul.txt"), Some("_nul.txt".to_string()));
+        assert_eq!(sanitize_file_name("com1.tar.gz"), Some("_com1.tar.gz".to_string()));
+        assert_eq!(sanitize_file_name("console.log"), Some("console.log".to_string()));
+    }
+
+    #[test]
+    fn trims_trailing_dots_and_spaces() {
+        assert_eq!(sanitize_file_name("file.txt. . "), Some("file.txt".to_string()));
+        assert_eq!(sanitize_file_name("..."), None);
+        assert_eq!(sanitize_file_name("   "), None);
+    }
+
+    #[test]
+    fn truncates_long_names_keeping_extension() {
+        let name = format!("{}.txt", "a".repeat(300));
+        let sanitized = sanitize_file_name(&name).unwrap();
+        assert_eq!(sanitized.len(), MAX_COMPONENT_BYTES);
+        assert!(sanitized.ends_with(".txt"));
+
+        let name = "文".repeat(200);
+        let sanitized = sanitize_file_name(&name).unwrap();
+        assert!(sanitized.len() <= MAX_COMPONENT_BYTES);
+        assert!(sanitized.chars().all(|c| c == '文'));
+    }
+}
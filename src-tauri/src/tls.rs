@@ -0,0 +1,113 @@
+use crate::config::{self, Settings};
+use anyhow::Result;
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, PrivateKey, ServerName};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+// 本机的自签名证书，首次启动时生成并保存在配置目录
+pub struct Identity {
+    pub cert_der: Vec<u8>,
+    pub key_der: Vec<u8>,
+    pub fingerprint: String,
+}
+
+static IDENTITY: OnceLock<Identity> = OnceLock::new();
+
+// 首次启动时发现服务和传输服务可能同时要证书，只能由一处生成并写盘，
+// 否则本次使用的指纹会和留在磁盘上的不一致，重启后已配对的设备就对不上了
+static IDENTITY_INIT: Mutex<()> = Mutex::new(());
+
+pub fn identity() -> Result<&'static Identity> {
+    if let Some(identity) = IDENTITY.get() {
+        return Ok(identity);
+    }
+    let _guard = IDENTITY_INIT.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(identity) = IDENTITY.get() {
+        return Ok(identity);
+    }
+    let identity = load_or_create_identity()?;
+    Ok(IDENTITY.get_or_init(|| identity))
+}
+
+// 明文模式下不公布指纹，对方就会用 http 连接
+pub fn local_fingerprint() -> Option<String> {
+    if Settings::load().allow_plaintext {
+        return None;
+    }
+    identity().ok().map(|identity| identity.fingerprint.clone())
+}
+
+pub fn fingerprint(cert_der: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(cert_der))
+}
+
+fn load_or_create_identity() -> Result<Identity> {
+    let dir = config::config_dir().join("tls");
+    let cert_path = dir.join("cert.der");
+    let key_path = dir.join("key.der");
+
+    if let (Ok(cert_der), Ok(key_der)) = (std::fs::read(&cert_path), std::fs::read(&key_path)) {
+        let fingerprint = fingerprint(&cert_der);
+        return Ok(Identity { cert_der, key_der, fingerprint });
+    }
+
+    let cert = rcgen::generate_simple_self_signed(vec!["landrop.local".to_string()])?;
+    let cert_der = cert.serialize_der()?;
+    let key_der = cert.serialize_private_key_der();
+
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(&cert_path, &cert_der)?;
+    config::write_private(&key_path, &key_der)?;
+
+    let fingerprint = fingerprint(&cert_der);
+    Ok(Identity { cert_der, key_der, fingerprint })
+}
+
+// 自签名证书没有 CA 可以校验，只认与固定指纹一致的证书
+struct PinnedCertVerifier {
+    fingerprint: String,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if fingerprint(&end_entity.0) == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General("Certificate fingerprint mismatch".to_string()))
+        }
+    }
+}
+
+pub fn acceptor() -> Result<tokio_rustls::TlsAcceptor> {
+    let identity = identity()?;
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![Certificate(identity.cert_der.clone())],
+            PrivateKey(identity.key_der.clone()),
+        )?;
+
+    Ok(tokio_rustls::TlsAcceptor::from(Arc::new(config)))
+}
+
+pub fn pinned_client(fingerprint: &str, builder: reqwest::ClientBuilder) -> Result<reqwest::Client> {
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier {
+            fingerprint: fingerprint.to_string(),
+        }))
+        .with_no_client_auth();
+
+    Ok(builder.use_preconfigured_tls(config).build()?)
+}
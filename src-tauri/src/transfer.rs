@@ -1,40 +1,192 @@
-use crate::types::{Device, TransferData, TransferMessage, TransferProgress, TransferStatus};
+use crate::config::{self, Settings};
+use crate::events::EventSink;
+use crate::history::{self, History};
+use crate::pairing::{self, KeyExchange, TrustStore};
+use crate::peers;
+use crate::progress::{self, ProgressTracker};
+use crate::sanitize;
+use crate::tls;
+use crate::types::{
+    ConflictPolicy, Device, HistoryEntry, HistoryQuery, ManifestEntry, PairConfirm, PairRequest, PairedDevice, ResumeInfo, RoutingRule, StaticPeer, TransferData,
+    TransferMessage, TransferProgress, TransferStatus,
+};
 use anyhow::Result;
 use axum::{
-    extract::{State as AxumState},
+    extract::{DefaultBodyLimit, Multipart, Path as AxumPath, Request, State as AxumState},
     http::StatusCode,
-    response::Json,
+    middleware::{self, Next},
+    response::{Json, Response},
     routing::{get, post},
-    Router,
+    Extension, Router,
 };
-use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
+use futures::StreamExt;
+use reqwest::multipart::{Form, Part};
+use sha2::{Digest, Sha256};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tauri::{AppHandle, Manager};
-use tokio::sync::{broadcast, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{broadcast, oneshot, Mutex};
+use tokio_util::io::ReaderStream;
+use hyper_util::rt::TokioIo;
+use tower::Service;
 use tower_http::cors::CorsLayer;
 
+// 未完成的文件放在默认接收目录下的这个子目录里，重启后仍可续传
+const PARTIAL_DIR: &str = ".landrop-partial";
+
+// 配置的端口被占用时依次尝试后面这么多个端口，仍然不行就交给系统分配
+const PORT_FALLBACK_ATTEMPTS: u16 = 10;
+
+// 探测对方候选地址时单个地址的连接超时
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// 接收方在这段时间内没有回应就视为拒绝
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
 pub struct TransferService {
-    device: Device,
+    // 改名后立即生效，服务端和发出的消息共用这一份
+    device: Arc<Mutex<Device>>,
     transfers: Arc<Mutex<HashMap<String, TransferStatus>>>,
     progress_sender: broadcast::Sender<TransferProgress>,
+    pending_requests: Arc<Mutex<HashMap<String, oneshot::Sender<RequestDecision>>>>,
+    // 已同意的请求及其尚未收到的文件
+    approved_requests: Arc<Mutex<HashMap<String, ApprovedRequest>>>,
+    trust_store: Arc<Mutex<TrustStore>>,
+    // 对方发起、等待本机用户确认的配对
+    incoming_pairings: Arc<Mutex<HashMap<String, IncomingPairing>>>,
+    // 本机发起、等待本机用户核对确认码的配对
+    outgoing_pairings: Arc<Mutex<HashMap<String, OutgoingPairing>>>,
+    settings: Arc<Mutex<Settings>>,
+    history: Arc<Mutex<History>>,
+    // 按证书指纹缓存的客户端，连接链路本地地址的客户端还要按占位主机名区分
+    clients: Arc<Mutex<HashMap<String, reqwest::Client>>>,
+    // 每台设备上次请求成功的地址
+    reachable_addresses: Arc<Mutex<HashMap<String, IpAddr>>>,
+}
+
+struct ApprovedRequest {
+    // 相对路径 -> 文件大小
+    files: HashMap<String, u64>,
+    conflict_policy: ConflictPolicy,
+    batch_tracker: Option<Arc<Mutex<ProgressTracker>>>,
+    // 正在上传的文件数，以及最近一次开始或收完文件的时间；发送方中途放弃时据此作废
+    uploads: Arc<AtomicUsize>,
+    last_activity: Instant,
+}
+
+// 上传期间持有，单个大文件传得再久请求也不会过期
+struct UploadGuard(Arc<AtomicUsize>);
+
+impl UploadGuard {
+    fn new(uploads: Arc<AtomicUsize>) -> Self {
+        uploads.fetch_add(1, Ordering::Relaxed);
+        UploadGuard(uploads)
+    }
+}
+
+impl Drop for UploadGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// 同意之后超过 REQUEST_TIMEOUT 没有新文件传来的请求作废，整批的进度标记为失败，不会一直显示传输中
+async fn expire_approved_requests(approved_requests: &mut HashMap<String, ApprovedRequest>) {
+    let expired: Vec<String> = approved_requests
+        .iter()
+        .filter(|(_, approved)| {
+            approved.uploads.load(Ordering::Relaxed) == 0 && approved.last_activity.elapsed() >= REQUEST_TIMEOUT
+        })
+        .map(|(request_id, _)| request_id.clone())
+        .collect();
+    for request_id in expired {
+        let Some(approved) = approved_requests.remove(&request_id) else {
+            continue;
+        };
+        if let Some(batch_tracker) = approved.batch_tracker {
+            let result = Err(anyhow::anyhow!("Sender stopped before all files arrived"));
+            batch_tracker.lock().await.finish(&result).await;
+        }
+    }
+}
+
+// 用户对接收请求的回应；conflict_policy 只在设置为 ask 时使用
+struct RequestDecision {
+    accept: bool,
+    conflict_policy: Option<ConflictPolicy>,
+}
+
+// 本地待发送的文件及其在接收方的相对路径
+struct LocalFile {
+    path: PathBuf,
+    relative_path: String,
+    size: u64,
 }
 
+struct OutgoingPairing {
+    device: Device,
+    key: Vec<u8>,
+    fingerprint: Option<String>,
+}
+
+struct IncomingPairing {
+    // 每次配对请求随机生成，用户的决定只对界面上显示的那一次有效
+    pairing_id: String,
+    device: Device,
+    key: Vec<u8>,
+    decision_sender: Option<oneshot::Sender<bool>>,
+    decision_receiver: Option<oneshot::Receiver<bool>>,
+    created: Instant,
+}
+
+// 通过签名校验的请求方设备 id
+#[derive(Clone)]
+struct AuthenticatedPeer(String);
+
+// 接收方校验摘要失败，调用方可以据此和网络错误区分开
+#[derive(Debug, thiserror::Error)]
+#[error("Integrity check failed for {0}")]
+pub struct VerificationFailed(pub String);
+
 impl TransferService {
     pub fn new() -> Result<Self> {
         let device = Device::current()?;
         let (progress_sender, _) = broadcast::channel(100);
         
         Ok(TransferService {
-            device,
+            device: Arc::new(Mutex::new(device)),
             transfers: Arc::new(Mutex::new(HashMap::new())),
             progress_sender,
+            pending_requests: Arc::new(Mutex::new(HashMap::new())),
+            approved_requests: Arc::new(Mutex::new(HashMap::new())),
+            trust_store: Arc::new(Mutex::new(TrustStore::load())),
+            incoming_pairings: Arc::new(Mutex::new(HashMap::new())),
+            outgoing_pairings: Arc::new(Mutex::new(HashMap::new())),
+            settings: Arc::new(Mutex::new(Settings::load())),
+            history: Arc::new(Mutex::new(History::load())),
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            reachable_addresses: Arc::new(Mutex::new(HashMap::new())),
         })
     }
 
-    pub async fn start_server(&self, app_handle: AppHandle) -> Result<()> {
+    pub async fn start_server(&mut self, events: Arc<dyn EventSink>) -> Result<()> {
+        let configured_port = self.settings.lock().await.transfer_port;
+        let listener = bind_with_fallback(configured_port).await?;
+        let port = listener.local_addr()?.port();
+        if port != configured_port {
+            eprintln!("Port {} is in use, falling back to {}", configured_port, port);
+        }
+        // 之后生成的设备信息和发现广播都会带上实际端口
+        config::set_transfer_port(port);
+        self.device.lock().await.port = port;
+
         let transfers = self.transfers.clone();
         let progress_sender = self.progress_sender.clone();
         let device = self.device.clone();
@@ -42,24 +194,87 @@ impl TransferService {
         let shared_state = SharedState {
             transfers,
             progress_sender,
+            pending_requests: self.pending_requests.clone(),
+            approved_requests: self.approved_requests.clone(),
+            trust_store: self.trust_store.clone(),
+            incoming_pairings: self.incoming_pairings.clone(),
+            settings: self.settings.clone(),
+            history: self.history.clone(),
+            seen_nonces: Arc::new(Mutex::new(HashMap::new())),
             device,
-            app_handle,
+            events,
         };
 
-        let app = Router::new()
-            .route("/api/receive/file", post(receive_file))
+        // 收发相关的接口只对已配对并且签名正确的设备开放
+        let protected = Router::new()
+            .route("/api/receive/request", post(receive_request))
+            // 文件以流的形式写盘，只有这个接口不需要默认的 2MB 请求体限制；
+            // 其余接口用 Json 整个读进内存，保留限制
+            .route("/api/receive/file", post(receive_file).layer(DefaultBodyLimit::disable()))
             .route("/api/receive/text", post(receive_text))
+            .route("/api/transfer/:transfer_id", get(get_resume_info))
+            .route_layer(middleware::from_fn_with_state(shared_state.clone(), authenticate));
+
+        let app = Router::new()
+            .merge(protected)
+            .route("/api/pair/request", post(pair_request))
+            .route("/api/pair/confirm", post(pair_confirm))
             .route("/api/ping", get(ping))
             .route("/api/device", get(get_device_info))
             .layer(CorsLayer::permissive())
             .with_state(shared_state);
 
-        let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
-        println!("Transfer server listening on http://0.0.0.0:8080");
-        
+        if self.settings.lock().await.allow_plaintext {
+            eprintln!("Transfer server listening on http://{}", listener.local_addr()?);
+            
+            tokio::spawn(async move {
+                if let Err(e) = axum::serve(listener, app).await {
+                    eprintln!("Server error: {}", e);
+                }
+            });
+
+            return Ok(());
+        }
+
+        let acceptor = tls::acceptor()?;
+        eprintln!(
+            "Transfer server listening on https://{} ({})",
+            listener.local_addr()?,
+            tls::identity()?.fingerprint
+        );
+
         tokio::spawn(async move {
-            if let Err(e) = axum::serve(listener, app).await {
-                eprintln!("Server error: {}", e);
+            loop {
+                let (stream, _) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        eprintln!("Server error: {}", e);
+                        continue;
+                    }
+                };
+
+                let acceptor = acceptor.clone();
+                let app = app.clone();
+                tokio::spawn(async move {
+                    let stream = match acceptor.accept(stream).await {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            eprintln!("TLS handshake failed: {}", e);
+                            return;
+                        }
+                    };
+
+                    let service = hyper::service::service_fn(move |request: hyper::Request<hyper::body::Incoming>| {
+                        app.clone().call(request)
+                    });
+                    if let Err(e) = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .with_upgrades()
+                        .await
+                    {
+                        eprintln!("Connection error: {}", e);
+                    }
+                });
             }
         });
 
@@ -67,25 +282,151 @@ impl TransferService {
     }
 
     pub async fn send_file(&self, file_path: &str, target_device: &Device) -> Result<()> {
-        let file_data = fs::read(file_path)?;
-        let file_name = PathBuf::from(file_path)
-            .file_name()
-            .and_then(|name| name.to_str())
+        self.send_paths(&[file_path.to_string()], target_device).await
+    }
+
+    // 发送若干文件和文件夹，文件夹会被展开，接收方按相对路径还原目录结构
+    pub async fn send_paths(&self, paths: &[String], target_device: &Device) -> Result<()> {
+        let paths = paths.to_vec();
+        let entries = tokio::task::spawn_blocking(move || collect_files(&paths)).await??;
+        if entries.is_empty() {
+            return Err(anyhow::anyhow!("Nothing to send"));
+        }
+
+        let total_size: u64 = entries.iter().map(|entry| entry.size).sum();
+        let batch_name = entries[0]
+            .relative_path
+            .split('/')
+            .next()
             .unwrap_or("unknown")
             .to_string();
+        let manifest: Vec<ManifestEntry> = entries
+            .iter()
+            .map(|entry| ManifestEntry {
+                path: entry.relative_path.clone(),
+                size: entry.size,
+                mime_type: Some(mime_guess::from_path(&entry.path).first_or_octet_stream().to_string()),
+            })
+            .collect();
+
+        let started = Instant::now();
+        let (request_id, skipped) = match self
+            .request_permission(&batch_name, total_size, manifest, target_device)
+            .await
+        {
+            Ok(reply) => reply,
+            Err(e) => {
+                record_history(&self.history, HistoryEntry {
+                    duration_ms: started.elapsed().as_millis() as u64,
+                    outcome: "failed".to_string(),
+                    error: Some(e.to_string()),
+                    ..history_entry("send", "file", target_device, &batch_name, total_size)
+                })
+                .await;
+                return Err(e);
+            }
+        };
+
+        // 接收方已有且选择跳过的文件不再上传
+        let (skipped, entries): (Vec<LocalFile>, Vec<LocalFile>) = entries
+            .into_iter()
+            .partition(|entry| skipped.contains(&entry.relative_path));
+        for entry in &skipped {
+            record_history(&self.history, HistoryEntry {
+                path: Some(entry.path.to_string_lossy().to_string()),
+                outcome: "skipped".to_string(),
+                ..history_entry("send", "file", target_device, &entry.relative_path, entry.size)
+            })
+            .await;
+        }
+        let total_size: u64 = entries.iter().map(|entry| entry.size).sum();
+        if entries.is_empty() {
+            eprintln!("All files already exist on {}, nothing to send", target_device.name);
+            return Ok(());
+        }
+
+        // 多个文件时额外记录整批的进度，transfer_id 即 request_id
+        let batch_tracker = if entries.len() > 1 {
+            Some(Arc::new(Mutex::new(
+                ProgressTracker::start(
+                    self.transfers.clone(),
+                    self.progress_sender.clone(),
+                    &request_id,
+                    &batch_name,
+                    "send",
+                    0,
+                    total_size,
+                )
+                .await,
+            )))
+        } else {
+            None
+        };
+
+        let mut result = Ok(());
+        for entry in &entries {
+            result = self
+                .upload_file(entry, &request_id, target_device, batch_tracker.clone())
+                .await;
+            if result.is_err() {
+                break;
+            }
+        }
 
-        let mime_type = mime_guess::from_path(file_path)
+        if let Some(batch_tracker) = batch_tracker {
+            batch_tracker.lock().await.finish(&result).await;
+        }
+        result
+    }
+
+    async fn upload_file(
+        &self,
+        entry: &LocalFile,
+        request_id: &str,
+        target_device: &Device,
+        batch_tracker: Option<Arc<Mutex<ProgressTracker>>>,
+    ) -> Result<()> {
+        let started = Instant::now();
+        let file_path = entry.path.to_string_lossy().to_string();
+        let mut file = fs::File::open(&entry.path).await?;
+        let metadata = file.metadata().await?;
+        let size = metadata.len();
+        let file_name = entry.relative_path.clone();
+
+        let mime_type = mime_guess::from_path(&entry.path)
             .first_or_octet_stream()
             .to_string();
 
+        // 由文件和目标推导出 transfer_id，两端重启后都能对上同一个未完成的传输
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let transfer_id = make_transfer_id(&file_path, size, modified, target_device);
+
+        let offset = self.resume_offset(&file_path, &transfer_id, size, target_device).await;
+        if offset > 0 {
+            eprintln!("Resuming {} from byte {}", file_name, offset);
+            file.seek(std::io::SeekFrom::Start(offset)).await?;
+        }
+        // 摘要覆盖整个文件，续传时先补上已发送的那段前缀
+        let hasher = prefix_hasher(&entry.path, offset).await?;
+        if let Some(batch_tracker) = &batch_tracker {
+            batch_tracker.lock().await.advance(offset).await;
+        }
+
         let transfer_message = TransferMessage {
             message_type: "file".to_string(),
-            sender: self.device.clone(),
+            sender: self.device.lock().await.clone(),
             data: TransferData::File {
-                name: file_name,
-                size: file_data.len() as u64,
-                mime_type,
-                data: file_data,
+                name: file_name.clone(),
+                size,
+                mime_type: mime_type.clone(),
+                transfer_id: transfer_id.clone(),
+                offset,
+                request_id: request_id.to_string(),
             },
             timestamp: SystemTime::now()
                 .duration_since(UNIX_EPOCH)
@@ -93,13 +434,179 @@ impl TransferService {
                 .as_secs(),
         };
 
-        self.send_to_device(&transfer_message, target_device).await
+        let tracker = Arc::new(Mutex::new(
+            ProgressTracker::start(
+                self.transfers.clone(),
+                self.progress_sender.clone(),
+                &transfer_id,
+                &file_name,
+                "send",
+                offset,
+                size,
+            )
+            .await,
+        ));
+
+        // 元数据放在 meta 字段，文件内容从磁盘分块读出直接写进请求体，每读一块记一次进度。
+        // 读的同时计算 SHA-256，读完后通过 sha256 字段跟在文件内容后面发出
+        let (digest_sender, digest_receiver) = oneshot::channel::<String>();
+        let digest = Arc::new(std::sync::OnceLock::new());
+        let stream = futures::stream::unfold(
+            (ReaderStream::new(file), hasher, Some(digest_sender), digest.clone(), tracker.clone(), batch_tracker),
+            |(mut reader, mut hasher, mut digest_sender, digest, tracker, batch_tracker)| async move {
+                let Some(chunk) = reader.next().await else {
+                    if let Some(digest_sender) = digest_sender.take() {
+                        let hex = format!("{:x}", hasher.finalize());
+                        let _ = digest.set(hex.clone());
+                        let _ = digest_sender.send(hex);
+                    }
+                    return None;
+                };
+                if let Ok(bytes) = &chunk {
+                    hasher.update(bytes);
+                    tracker.lock().await.advance(bytes.len() as u64).await;
+                    if let Some(batch_tracker) = &batch_tracker {
+                        batch_tracker.lock().await.advance(bytes.len() as u64).await;
+                    }
+                }
+                Some((chunk, (reader, hasher, digest_sender, digest, tracker, batch_tracker)))
+            },
+        );
+        let form = Form::new()
+            .text("meta", serde_json::to_string(&transfer_message)?)
+            .part(
+                "file",
+                Part::stream_with_length(reqwest::Body::wrap_stream(stream), size - offset)
+                    .file_name(file_name.clone())
+                    .mime_str(&mime_type)?,
+            )
+            .part(
+                "sha256",
+                Part::stream(reqwest::Body::wrap_stream(futures::stream::once(digest_receiver))),
+            );
+
+        let result = async {
+            let (request, ip) = self
+                .request(reqwest::Method::POST, target_device, "/api/receive/file")
+                .await?;
+            let response = self.send(target_device, ip, request.multipart(form)).await?;
+            Ok(response.status())
+        }
+        .await;
+
+        // 接收方校验摘要失败时单独标记，和网络错误区分开
+        let (result, outcome) = match result {
+            Ok(status) if status.is_success() => (Ok(()), "completed"),
+            Ok(reqwest::StatusCode::UNPROCESSABLE_ENTITY) => (
+                Err(VerificationFailed(file_name.clone()).into()),
+                "verification_failed",
+            ),
+            Ok(status) => (Err(anyhow::anyhow!("Transfer failed: {}", status)), "failed"),
+            Err(e) => (Err(e), "failed"),
+        };
+        let error = result.as_ref().err().map(ToString::to_string);
+
+        tracker.lock().await.set_status(outcome, error.clone()).await;
+        record_history(&self.history, HistoryEntry {
+            path: Some(file_path),
+            sha256: digest.get().cloned(),
+            duration_ms: started.elapsed().as_millis() as u64,
+            outcome: outcome.to_string(),
+            error,
+            ..history_entry("send", "file", target_device, &file_name, size)
+        })
+        .await;
+        if result.is_ok() {
+            eprintln!("Transfer successful");
+        }
+        result
+    }
+
+    // 把要发送的文件清单告诉接收方，等待对方在界面上同意或拒绝
+    async fn request_permission(
+        &self,
+        name: &str,
+        size: u64,
+        files: Vec<ManifestEntry>,
+        target_device: &Device,
+    ) -> Result<(String, Vec<String>)> {
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let request_message = TransferMessage {
+            message_type: "file-request".to_string(),
+            sender: self.device.lock().await.clone(),
+            data: TransferData::FileRequest {
+                request_id: request_id.clone(),
+                name: name.to_string(),
+                size,
+                file_count: files.len() as u64,
+                files,
+            },
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        };
+
+        let (request, ip) = self
+            .request(reqwest::Method::POST, target_device, "/api/receive/request")
+            .await?;
+        let response = self.send(target_device, ip, request.json(&request_message)).await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("File request failed: {}", response.status()));
+        }
+
+        let reply: serde_json::Value = response.json().await?;
+        if reply["accepted"].as_bool() != Some(true) {
+            let reason = reply["reason"].as_str().unwrap_or("declined");
+            return Err(anyhow::anyhow!("Transfer {} by receiver", reason));
+        }
+
+        let skipped = reply["skipped"]
+            .as_array()
+            .map(|paths| {
+                paths
+                    .iter()
+                    .filter_map(|path| path.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok((request_id, skipped))
+    }
+
+    // 询问接收方已有多少字节，并校验这段前缀与本地文件一致；任何异常都从头开始
+    async fn resume_offset(&self, file_path: &str, transfer_id: &str, size: u64, target_device: &Device) -> u64 {
+        let path = format!("/api/transfer/{}", transfer_id);
+        let (request, ip) = match self.request(reqwest::Method::GET, target_device, &path).await {
+            Ok(request) => request,
+            Err(_) => return 0,
+        };
+        let info = match self.send(target_device, ip, request).await {
+            Ok(response) if response.status().is_success() => match response.json::<ResumeInfo>().await {
+                Ok(info) => info,
+                Err(_) => return 0,
+            },
+            _ => return 0,
+        };
+
+        if info.offset == 0 || info.offset > size {
+            return 0;
+        }
+
+        match (info.sha256, hash_prefix(Path::new(file_path), info.offset).await) {
+            (Some(remote), Ok(local)) if remote == local => info.offset,
+            _ => {
+                eprintln!("Partial data on receiver does not match, restarting transfer");
+                0
+            }
+        }
     }
 
     pub async fn send_text(&self, text: &str, target_device: &Device) -> Result<()> {
         let transfer_message = TransferMessage {
             message_type: "text".to_string(),
-            sender: self.device.clone(),
+            sender: self.device.lock().await.clone(),
             data: TransferData::Text {
                 content: text.to_string(),
             },
@@ -109,32 +616,401 @@ impl TransferService {
                 .as_secs(),
         };
 
-        self.send_to_device(&transfer_message, target_device).await
+        let started = Instant::now();
+        let result = self.send_to_device(&transfer_message, target_device).await;
+        record_history(&self.history, HistoryEntry {
+            duration_ms: started.elapsed().as_millis() as u64,
+            outcome: if result.is_ok() { "completed" } else { "failed" }.to_string(),
+            error: result.as_ref().err().map(ToString::to_string),
+            ..history_entry("send", "text", target_device, &history::text_preview(text), text.len() as u64)
+        })
+        .await;
+        result
     }
 
     async fn send_to_device(&self, message: &TransferMessage, target_device: &Device) -> Result<()> {
-        let client = reqwest::Client::new();
-        let url = match &message.data {
-            TransferData::File { .. } => format!("http://{}:{}/api/receive/file", target_device.ip, target_device.port),
-            TransferData::Text { .. } => format!("http://{}:{}/api/receive/text", target_device.ip, target_device.port),
+        let path = match &message.data {
+            TransferData::Text { .. } => "/api/receive/text",
             _ => return Err(anyhow::anyhow!("Unsupported transfer data type")),
         };
 
-        let response = client
-            .post(&url)
-            .json(message)
-            .send()
-            .await?;
+        let (request, ip) = self.request(reqwest::Method::POST, target_device, path).await?;
+        let response = self.send(target_device, ip, request.json(message)).await?;
 
-        if response.status().is_success() {
-            println!("Transfer successful");
-        } else {
-            println!("Transfer failed: {}", response.status());
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("Transfer failed: {}", response.status()));
+        }
+
+        eprintln!("Transfer successful");
+        Ok(())
+    }
+
+    // 配对时记下的指纹优先，其次是对方在发现消息里公布的指纹
+    async fn pinned_fingerprint(&self, target_device: &Device) -> Option<String> {
+        self.trust_store
+            .lock()
+            .await
+            .fingerprint_for(&target_device.id)
+            .or_else(|| target_device.cert_fingerprint.clone())
+    }
+
+    // 选择连接对方用的客户端、URL 和地址；没有指纹时只有在允许明文的情况下才退回 http
+    async fn endpoint(&self, target_device: &Device, path: &str) -> Result<(reqwest::Client, String, IpAddr)> {
+        let fingerprint = self.pinned_fingerprint(target_device).await;
+        if fingerprint.is_none() && !self.settings.lock().await.allow_plaintext {
+            return Err(anyhow::anyhow!(
+                "Device {} does not offer an encrypted connection",
+                target_device.name
+            ));
+        }
+
+        // IPv6 地址由 SocketAddr 加上方括号；带网卡编号的链路本地地址写不进 URL，
+        // 换成占位主机名，再让客户端把它解析回原地址
+        let ip = self.reachable_address(target_device).await?;
+        let address = target_device.socket_address(ip);
+        let scoped_host = match address {
+            SocketAddr::V6(v6) if v6.scope_id() != 0 => Some(scoped_host(&v6)),
+            _ => None,
+        };
+        let host = match &scoped_host {
+            Some(host) => format!("{}:{}", host, address.port()),
+            None => address.to_string(),
+        };
+
+        let key = format!(
+            "{}@{}",
+            fingerprint.as_deref().unwrap_or_default(),
+            scoped_host.as_deref().unwrap_or_default()
+        );
+        let mut clients = self.clients.lock().await;
+        let client = match clients.get(&key) {
+            Some(client) => client.clone(),
+            None => {
+                let mut builder = reqwest::Client::builder();
+                if let Some(scoped_host) = &scoped_host {
+                    builder = builder.resolve(scoped_host, address);
+                }
+                let client = match &fingerprint {
+                    Some(fingerprint) => tls::pinned_client(fingerprint, builder)?,
+                    None => builder.build()?,
+                };
+                clients.insert(key, client.clone());
+                client
+            }
+        };
+
+        let scheme = if fingerprint.is_some() { "https" } else { "http" };
+        Ok((client, format!("{}://{}{}", scheme, host, path), ip))
+    }
+
+    // 之前请求成功过的地址直接使用，不再探测。否则先试收到对方报文的来源地址，连不上再依次试对方自报的地址：
+    // 自报的地址里可能有 Docker 网桥这类本机也有的地址，连上的其实是自己，所以不能同时尝试、谁快用谁。
+    // 请求体是流，没法失败后换地址重发，所以先探测
+    async fn reachable_address(&self, target_device: &Device) -> Result<IpAddr> {
+        let candidates = target_device.candidate_addresses();
+        if candidates.len() == 1 {
+            return Ok(candidates[0]);
+        }
+
+        let known = self.reachable_addresses.lock().await.get(&target_device.id).copied();
+        if let Some(known) = known.filter(|ip| candidates.contains(ip)) {
+            return Ok(known);
+        }
+
+        let mut errors = Vec::new();
+        for ip in candidates {
+            match probe_address(target_device.socket_address(ip)).await {
+                Ok(()) => return Ok(ip),
+                Err(e) => errors.push(e.to_string()),
+            }
+        }
+        Err(anyhow::anyhow!(
+            "Device {} is not reachable at any known address ({})",
+            target_device.name,
+            errors.join(", ")
+        ))
+    }
+
+    // 请求成功才记住这个地址；连接失败（包括证书指纹不符）时忘掉它，下次重新探测
+    async fn send(&self, target_device: &Device, ip: IpAddr, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        match request.send().await {
+            Ok(response) => {
+                if response.status().is_success() {
+                    self.reachable_addresses.lock().await.insert(target_device.id.clone(), ip);
+                }
+                Ok(response)
+            }
+            Err(e) => {
+                if e.is_connect() {
+                    self.reachable_addresses.lock().await.remove(&target_device.id);
+                }
+                Err(e.into())
+            }
+        }
+    }
+
+    // 构造一个带配对密钥签名的请求，未配对的设备直接报错
+    async fn request(
+        &self,
+        method: reqwest::Method,
+        target_device: &Device,
+        path: &str,
+    ) -> Result<(reqwest::RequestBuilder, IpAddr)> {
+        let key = self
+            .trust_store
+            .lock()
+            .await
+            .key_for(&target_device.id)
+            .ok_or_else(|| anyhow::anyhow!("Device {} is not paired", target_device.name))?;
+
+        let own_id = self.device.lock().await.id.clone();
+        let (client, url, ip) = self.endpoint(target_device, path).await?;
+        let mut builder = client.request(method.clone(), url);
+        for (name, value) in pairing::auth_headers(&key, &own_id, method.as_str(), path) {
+            builder = builder.header(name, value);
+        }
+        Ok((builder, ip))
+    }
+
+    // 发起配对，返回需要与对方屏幕核对的确认码
+    pub async fn start_pairing(&self, target_device: &Device) -> Result<String> {
+        let exchange = KeyExchange::generate();
+        let own_device = self.device.lock().await.clone();
+        let own_fingerprint = own_device.cert_fingerprint.clone();
+        let request = PairRequest {
+            device: own_device,
+            public_key: exchange.public_key(),
+        };
+
+        let fingerprint = self.pinned_fingerprint(target_device).await;
+        let (client, url, ip) = self.endpoint(target_device, "/api/pair/request").await?;
+        let response = self.send(target_device, ip, client.post(url).json(&request)).await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("Pairing request failed: {}", response.status()));
+        }
+
+        let reply: PairRequest = response.json().await?;
+        if reply.device.id != target_device.id {
+            return Err(anyhow::anyhow!("Unexpected device answered the pairing request"));
+        }
+
+        let (key, code) = exchange.finish(
+            &reply.public_key,
+            true,
+            own_fingerprint.as_deref().unwrap_or_default(),
+            fingerprint.as_deref().unwrap_or_default(),
+        )?;
+        self.outgoing_pairings
+            .lock()
+            .await
+            .insert(
+                target_device.id.clone(),
+                OutgoingPairing {
+                    device: target_device.clone(),
+                    key,
+                    fingerprint,
+                },
+            );
+
+        Ok(code)
+    }
+
+    // 对方发起的配对：用户核对 pair-request 事件里的确认码之后调用。
+    // 同一设备 id 又来了新的请求时旧的会被替换，按 pairing_id 对应，确保同意的正是显示过的那个确认码
+    pub async fn respond_pairing(&self, pairing_id: &str, accept: bool) -> Result<()> {
+        let mut pairings = self.incoming_pairings.lock().await;
+        let pairing = pairings
+            .values_mut()
+            .find(|pairing| pairing.pairing_id == pairing_id)
+            .ok_or_else(|| anyhow::anyhow!("No pending pairing {}", pairing_id))?;
+        if let Some(sender) = pairing.decision_sender.take() {
+            let _ = sender.send(accept);
+        }
+        Ok(())
+    }
+
+    // 本机发起的配对：用户核对 start_pairing 返回的确认码之后调用，返回是否配对成功
+    pub async fn confirm_pairing(&self, device_id: &str, accept: bool) -> Result<bool> {
+        let OutgoingPairing { device, key, fingerprint } = self
+            .outgoing_pairings
+            .lock()
+            .await
+            .remove(device_id)
+            .ok_or_else(|| anyhow::anyhow!("No pending pairing with {}", device_id))?;
+        if !accept {
+            return Ok(false);
+        }
+
+        let own_id = self.device.lock().await.id.clone();
+        let confirm = PairConfirm {
+            proof: pairing::confirmation_proof(&key, &own_id),
+            device_id: own_id,
+        };
+        let (client, url, ip) = self.endpoint(&device, "/api/pair/confirm").await?;
+        let response = self.send(&device, ip, client.post(url).json(&confirm)).await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("Pairing confirmation failed: {}", response.status()));
+        }
+
+        let reply: serde_json::Value = response.json().await?;
+        let accepted = reply["accepted"].as_bool() == Some(true);
+        if accepted {
+            self.trust_store.lock().await.insert(&device.id, &device.name, &key, fingerprint)?;
+            eprintln!("Paired with {}", device.name);
+        }
+
+        Ok(accepted)
+    }
+
+    pub async fn list_paired_devices(&self) -> Vec<PairedDevice> {
+        self.trust_store.lock().await.list()
+    }
+
+    pub async fn unpair_device(&self, device_id: &str) -> Result<()> {
+        self.trust_store.lock().await.remove(device_id)
+    }
+
+    pub async fn respond_request(
+        &self,
+        request_id: &str,
+        accept: bool,
+        conflict_policy: Option<ConflictPolicy>,
+    ) -> Result<()> {
+        let sender = self
+            .pending_requests
+            .lock()
+            .await
+            .remove(request_id)
+            .ok_or_else(|| anyhow::anyhow!("No pending request {}", request_id))?;
+        let _ = sender.send(RequestDecision { accept, conflict_policy });
+        Ok(())
+    }
+
+    // 命令行的 add-peer 等可能在别的进程里改过 settings.json，能读到就以磁盘上的为准
+    pub async fn settings(&self) -> Settings {
+        let mut settings = self.settings.lock().await;
+        if let Ok(latest) = Settings::try_load() {
+            *settings = latest;
         }
+        settings.clone()
+    }
 
+    // 修改前先从磁盘重新读取，不会用内存里的旧副本覆盖其他进程写入的内容；文件有误时不写回
+    async fn modify_settings<T>(&self, modify: impl FnOnce(&mut Settings) -> T) -> Result<T> {
+        let mut settings = self.settings.lock().await;
+        let mut latest = Settings::try_load()?;
+        let result = modify(&mut latest);
+        latest.save()?;
+        *settings = latest;
+        Ok(result)
+    }
+
+    pub async fn update_settings(&self, settings: Settings) -> Result<()> {
+        settings.save()?;
+        *self.settings.lock().await = settings;
+        // 设置里也可能改了设备名
+        self.device.lock().await.name = config::device_name()?;
         Ok(())
     }
 
+    // 发现广播、传输消息和配对请求都会立即使用新名称
+    pub async fn set_device_name(&self, name: Option<String>) -> Result<()> {
+        self.modify_settings(|settings| {
+            settings.device_name = name
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty());
+        })
+        .await?;
+
+        self.device.lock().await.name = config::device_name()?;
+        Ok(())
+    }
+
+    pub async fn receive_dir(&self) -> PathBuf {
+        self.settings.lock().await.receive_dir()
+    }
+
+    pub async fn set_receive_dir(&self, receive_dir: Option<PathBuf>) -> Result<()> {
+        if let Some(dir) = &receive_dir {
+            std::fs::create_dir_all(dir)?;
+        }
+        self.modify_settings(|settings| settings.receive_dir = receive_dir).await
+    }
+
+    pub async fn list_routing_rules(&self) -> Vec<RoutingRule> {
+        self.settings.lock().await.routing_rules.clone()
+    }
+
+    pub async fn add_routing_rule(&self, mut rule: RoutingRule) -> Result<RoutingRule> {
+        if rule.sender_id.is_none() && rule.mime_type.is_none() && rule.extension.is_none() {
+            return Err(anyhow::anyhow!("Routing rule needs at least one condition"));
+        }
+        if rule.id.is_empty() {
+            rule.id = uuid::Uuid::new_v4().to_string();
+        }
+
+        self.modify_settings(|settings| {
+            settings.routing_rules.retain(|existing| existing.id != rule.id);
+            settings.routing_rules.push(rule.clone());
+        })
+        .await?;
+        Ok(rule)
+    }
+
+    pub async fn remove_routing_rule(&self, rule_id: &str) -> Result<()> {
+        self.modify_settings(|settings| settings.routing_rules.retain(|rule| rule.id != rule_id))
+            .await
+    }
+
+    pub async fn list_static_peers(&self) -> Vec<StaticPeer> {
+        self.settings.lock().await.static_peers.clone()
+    }
+
+    // 先探测一次，连不上的地址不保存；返回对方的设备信息
+    pub async fn add_static_peer(&self, host: &str, port: u16) -> Result<Device> {
+        let host = host.trim();
+        let allow_plaintext = self.settings.lock().await.allow_plaintext;
+        let (device, _) = peers::probe(host, port, allow_plaintext).await?;
+
+        let peer = StaticPeer {
+            host: host.to_string(),
+            port,
+        };
+        self.modify_settings(|settings| {
+            if !settings.static_peers.contains(&peer) {
+                settings.static_peers.push(peer);
+            }
+        })
+        .await?;
+        Ok(device)
+    }
+
+    pub async fn remove_static_peer(&self, host: &str, port: u16) -> Result<()> {
+        self.modify_settings(|settings| {
+            settings
+                .static_peers
+                .retain(|peer| !(peer.host == host.trim() && peer.port == port));
+        })
+        .await
+    }
+
+    pub async fn query_history(&self, query: &HistoryQuery) -> Result<Vec<HistoryEntry>> {
+        self.history.lock().await.query(query)
+    }
+
+    pub async fn clear_history(&self) -> Result<()> {
+        self.history.lock().await.clear()
+    }
+
+    pub async fn list_transfers(&self) -> Vec<TransferStatus> {
+        expire_approved_requests(&mut *self.approved_requests.lock().await).await;
+        let mut transfers = self.transfers.lock().await;
+        progress::prune_finished(&mut transfers);
+        transfers.values().cloned().collect()
+    }
+
     pub fn subscribe_progress(&self) -> broadcast::Receiver<TransferProgress> {
         self.progress_sender.subscribe()
     }
@@ -144,61 +1020,877 @@ impl TransferService {
 struct SharedState {
     transfers: Arc<Mutex<HashMap<String, TransferStatus>>>,
     progress_sender: broadcast::Sender<TransferProgress>,
-    device: Device,
-    app_handle: AppHandle,
+    pending_requests: Arc<Mutex<HashMap<String, oneshot::Sender<RequestDecision>>>>,
+    approved_requests: Arc<Mutex<HashMap<String, ApprovedRequest>>>,
+    trust_store: Arc<Mutex<TrustStore>>,
+    incoming_pairings: Arc<Mutex<HashMap<String, IncomingPairing>>>,
+    settings: Arc<Mutex<Settings>>,
+    history: Arc<Mutex<History>>,
+    // 最近用过的 nonce，防止签名请求被重放
+    seen_nonces: Arc<Mutex<HashMap<String, u64>>>,
+    device: Arc<Mutex<Device>>,
+    events: Arc<dyn EventSink>,
 }
 
-async fn receive_file(
+async fn authenticate(
     AxumState(state): AxumState<SharedState>,
-    Json(message): Json<TransferMessage>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let headers = request.headers();
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let (Some(device_id), Some(timestamp), Some(nonce), Some(signature)) = (
+        header(pairing::HEADER_DEVICE),
+        header(pairing::HEADER_TIMESTAMP),
+        header(pairing::HEADER_NONCE),
+        header(pairing::HEADER_SIGNATURE),
+    ) else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+
+    let key = state
+        .trust_store
+        .lock()
+        .await
+        .key_for(&device_id)
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let now = pairing::now_secs();
+    let sent_at: u64 = timestamp.parse().map_err(|_| StatusCode::UNAUTHORIZED)?;
+    if now.abs_diff(sent_at) > pairing::MAX_CLOCK_SKEW_SECS {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let valid = pairing::verify_request(
+        &key,
+        request.method().as_str(),
+        request.uri().path(),
+        &device_id,
+        &timestamp,
+        &nonce,
+        &signature,
+    );
+    if !valid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let mut seen_nonces = state.seen_nonces.lock().await;
+    seen_nonces.retain(|_, seen_at| now.abs_diff(*seen_at) <= pairing::MAX_CLOCK_SKEW_SECS);
+    if seen_nonces.insert(nonce, sent_at).is_some() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    drop(seen_nonces);
+
+    request.extensions_mut().insert(AuthenticatedPeer(device_id));
+    Ok(next.run(request).await)
+}
+
+async fn pair_request(
+    AxumState(state): AxumState<SharedState>,
+    Json(request): Json<PairRequest>,
+) -> Result<Json<PairRequest>, StatusCode> {
+    let exchange = KeyExchange::generate();
+    let public_key = exchange.public_key();
+    let own_device = state.device.lock().await.clone();
+    let (key, code) = exchange
+        .finish(
+            &request.public_key,
+            false,
+            request.device.cert_fingerprint.as_deref().unwrap_or_default(),
+            own_device.cert_fingerprint.as_deref().unwrap_or_default(),
+        )
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let (decision_sender, decision_receiver) = oneshot::channel();
+    let pairing_id = uuid::Uuid::new_v4().to_string();
+    let mut pairings = state.incoming_pairings.lock().await;
+    pairings.retain(|_, pairing| pairing.created.elapsed() < REQUEST_TIMEOUT * 2);
+    pairings.insert(
+        request.device.id.clone(),
+        IncomingPairing {
+            pairing_id: pairing_id.clone(),
+            device: request.device.clone(),
+            key,
+            decision_sender: Some(decision_sender),
+            decision_receiver: Some(decision_receiver),
+            created: Instant::now(),
+        },
+    );
+    drop(pairings);
+
+    // 发送事件到前端，显示确认码让用户与对方屏幕核对
+    state.events.emit("pair-request", serde_json::json!({
+        "pairingId": pairing_id,
+        "device": request.device,
+        "code": code
+    }));
+
+    eprintln!("Pairing requested by {}", request.device.name);
+
+    Ok(Json(PairRequest {
+        device: own_device,
+        public_key,
+    }))
+}
+
+async fn pair_confirm(
+    AxumState(state): AxumState<SharedState>,
+    Json(confirm): Json<PairConfirm>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    if let TransferData::File { name, size, mime_type, data } = message.data {
-        // 获取下载目录
-        let downloads_dir = dirs::download_dir()
-            .unwrap_or_else(|| std::env::current_dir().unwrap());
-        
-        let file_path = downloads_dir.join(&name);
-        
-        // 写入文件
-        if let Err(e) = fs::write(&file_path, &data) {
-            eprintln!("Failed to write file: {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    let (device, key, decision_receiver) = {
+        let mut pairings = state.incoming_pairings.lock().await;
+        let pairing = pairings.get_mut(&confirm.device_id).ok_or(StatusCode::NOT_FOUND)?;
+        if !pairing::verify_confirmation(&pairing.key, &confirm.device_id, &confirm.proof) {
+            return Err(StatusCode::FORBIDDEN);
         }
+        let receiver = pairing.decision_receiver.take().ok_or(StatusCode::CONFLICT)?;
+        (pairing.device.clone(), pairing.key.clone(), receiver)
+    };
 
-        // 发送事件到前端
-        let _ = state.app_handle.emit_all("file-received", serde_json::json!({
-            "sender": message.sender,
-            "fileName": name,
-            "filePath": file_path.to_string_lossy(),
-            "fileSize": size,
-            "mimeType": mime_type,
-            "timestamp": message.timestamp
+    // 等待本机用户核对确认码
+    let decision = tokio::time::timeout(REQUEST_TIMEOUT, decision_receiver).await;
+    state.incoming_pairings.lock().await.remove(&confirm.device_id);
+
+    let accepted = matches!(decision, Ok(Ok(true)));
+    if accepted {
+        state
+            .trust_store
+            .lock()
+            .await
+            .insert(&device.id, &device.name, &key, device.cert_fingerprint.clone())
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        eprintln!("Paired with {}", device.name);
+    } else if decision.is_err() {
+        state.events.emit("pair-request-expired", serde_json::json!({
+            "deviceId": device.id
         }));
+    }
 
-        println!("File received: {} from {}", name, message.sender.name);
+    Ok(Json(serde_json::json!({ "accepted": accepted })))
+}
 
-        Ok(Json(serde_json::json!({
-            "status": "success",
-            "message": "File received successfully"
-        })))
+async fn receive_request(
+    AxumState(state): AxumState<SharedState>,
+    Extension(peer): Extension<AuthenticatedPeer>,
+    Json(message): Json<TransferMessage>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if message.sender.id != peer.0 {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let TransferData::FileRequest { request_id, name, size, file_count, files } = message.data else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+
+    // 按分流规则找出与目标目录里已有文件重名的条目。
+    // 清单里的路径在本机清理后不能落到同一个位置（比如 a:b 和 a_b），否则后传的会覆盖先传的
+    let settings = state.settings.lock().await.clone();
+    let mut conflicts = Vec::new();
+    let mut destinations = HashSet::new();
+    for entry in &files {
+        // 与 receive_file 一样按发送方给的类型分流，两边才会落到同一个目录
+        let mime_type = entry
+            .mime_type
+            .clone()
+            .unwrap_or_else(|| mime_guess::from_path(&entry.path).first_or_octet_stream().to_string());
+        let target_dir = settings.route(&message.sender.id, &entry.path, &mime_type);
+        let relative_path = sanitize::sanitize_relative_path(&entry.path).ok_or(StatusCode::BAD_REQUEST)?;
+        let destination = target_dir.join(relative_path);
+        if fs::try_exists(&destination).await.unwrap_or(false) {
+            conflicts.push(entry.path.clone());
+        }
+        if !destinations.insert(destination) {
+            eprintln!(
+                "Rejected file request {} from {}: {} collides with another file",
+                name, message.sender.name, entry.path
+            );
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    let configured_policy = settings.conflict_policy;
+
+    let (decision_sender, decision_receiver) = oneshot::channel();
+    state.pending_requests.lock().await.insert(request_id.clone(), decision_sender);
+
+    // 发送事件到前端，由用户决定是否接收
+    state.events.emit("file-request", serde_json::json!({
+        "requestId": request_id,
+        "sender": message.sender,
+        "fileName": name,
+        "fileSize": size,
+        "fileCount": file_count,
+        "conflicts": conflicts,
+        "conflictPolicy": configured_policy,
+        "timestamp": message.timestamp
+    }));
+
+    let (decision, reason) = match tokio::time::timeout(REQUEST_TIMEOUT, decision_receiver).await {
+        Ok(Ok(decision)) if decision.accept => (Some(decision), "accepted"),
+        Ok(_) => (None, "declined"),
+        Err(_) => {
+            state.pending_requests.lock().await.remove(&request_id);
+            state.events.emit("file-request-expired", serde_json::json!({
+                "requestId": request_id
+            }));
+            (None, "timed out")
+        }
+    };
+    if decision.is_none() {
+        record_history(&state.history, HistoryEntry {
+            outcome: if reason == "declined" { "declined" } else { "expired" }.to_string(),
+            ..history_entry("receive", "file", &message.sender, &name, size)
+        })
+        .await;
+    }
+
+    let mut skipped = Vec::new();
+    if let Some(decision) = &decision {
+        // 设置为 ask 时由用户在同意时选择，没选就按自动重命名处理
+        let conflict_policy = match configured_policy {
+            ConflictPolicy::Ask => decision
+                .conflict_policy
+                .filter(|policy| *policy != ConflictPolicy::Ask)
+                .unwrap_or(ConflictPolicy::Rename),
+            policy => policy,
+        };
+        if conflict_policy == ConflictPolicy::Skip {
+            skipped = conflicts;
+        }
+
+        let files: HashMap<String, u64> = files
+            .into_iter()
+            .filter(|entry| !skipped.contains(&entry.path))
+            .map(|entry| (entry.path, entry.size))
+            .collect();
+        let batch_size: u64 = files.values().sum();
+
+        // 多个文件时额外记录整批的进度，transfer_id 即 request_id
+        let batch_tracker = if files.len() > 1 {
+            Some(Arc::new(Mutex::new(
+                ProgressTracker::start(
+                    state.transfers.clone(),
+                    state.progress_sender.clone(),
+                    &request_id,
+                    &name,
+                    "receive",
+                    0,
+                    batch_size,
+                )
+                .await,
+            )))
+        } else {
+            None
+        };
+
+        if files.is_empty() {
+            // 全部跳过，不会再有文件上传
+            state.events.emit("file-request-completed", serde_json::json!({
+                "requestId": request_id
+            }));
+        } else {
+            let mut approved_requests = state.approved_requests.lock().await;
+            expire_approved_requests(&mut approved_requests).await;
+            approved_requests.insert(
+                request_id,
+                ApprovedRequest {
+                    files,
+                    conflict_policy,
+                    batch_tracker,
+                    uploads: Arc::new(AtomicUsize::new(0)),
+                    last_activity: Instant::now(),
+                },
+            );
+        }
+    }
+
+    eprintln!("File request for {} from {}: {}", name, message.sender.name, reason);
+
+    Ok(Json(serde_json::json!({
+        "accepted": decision.is_some(),
+        "reason": reason,
+        "skipped": skipped
+    })))
+}
+
+async fn receive_file(
+    AxumState(state): AxumState<SharedState>,
+    Extension(peer): Extension<AuthenticatedPeer>,
+    mut multipart: Multipart,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let mut message: Option<TransferMessage> = None;
+
+    while let Some(field) = multipart.next_field().await.map_err(|_| StatusCode::BAD_REQUEST)? {
+        match field.name() {
+            Some("meta") => {
+                let text = field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?;
+                message = Some(serde_json::from_str(&text).map_err(|_| StatusCode::BAD_REQUEST)?);
+            }
+            Some("file") => {
+                // meta 必须先于 file 到达，否则不知道该写到哪里
+                let message = message.take().ok_or(StatusCode::BAD_REQUEST)?;
+                let started = Instant::now();
+                if message.sender.id != peer.0 {
+                    return Err(StatusCode::FORBIDDEN);
+                }
+                let TransferData::File { name, size, mime_type, transfer_id, offset, request_id } = message.data else {
+                    return Err(StatusCode::BAD_REQUEST);
+                };
+                if !is_valid_transfer_id(&transfer_id) || offset > size {
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+
+                // 只接收用户已同意的请求里列出的文件
+                let mut approved_requests = state.approved_requests.lock().await;
+                expire_approved_requests(&mut approved_requests).await;
+                let (batch_tracker, conflict_policy, _upload) = match approved_requests.get_mut(&request_id) {
+                    Some(approved) if approved.files.get(&name) == Some(&size) => {
+                        approved.last_activity = Instant::now();
+                        (
+                            approved.batch_tracker.clone(),
+                            approved.conflict_policy,
+                            UploadGuard::new(approved.uploads.clone()),
+                        )
+                    }
+                    _ => return Err(StatusCode::FORBIDDEN),
+                };
+                drop(approved_requests);
+
+                // 续传文件统一放在默认接收目录，最终位置由分流规则决定
+                let settings = state.settings.lock().await.clone();
+                let receive_dir = settings.receive_dir();
+                let target_dir = settings.route(&message.sender.id, &name, &mime_type);
+
+                // 文件名来自发送方，清洗之后才能拼到目标目录下
+                let relative_path = sanitize::sanitize_relative_path(&name).ok_or(StatusCode::BAD_REQUEST)?;
+                if !is_safe_destination(&target_dir, &relative_path).await {
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+                let file_path = target_dir.join(&relative_path);
+                if let Some(parent) = file_path.parent() {
+                    fs::create_dir_all(parent)
+                        .await
+                        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+                }
+                let part_path = partial_path(&receive_dir, &transfer_id);
+
+                // 续传时已有的字节数必须与发送方给出的 offset 一致
+                let existing = fs::metadata(&part_path).await.map(|m| m.len()).unwrap_or(0);
+                if offset != 0 && offset != existing {
+                    return Err(StatusCode::CONFLICT);
+                }
+
+                let mut tracker = ProgressTracker::start(
+                    state.transfers.clone(),
+                    state.progress_sender.clone(),
+                    &transfer_id,
+                    &name,
+                    "receive",
+                    offset,
+                    size,
+                )
+                .await;
+                if let Some(batch_tracker) = &batch_tracker {
+                    batch_tracker.lock().await.advance(offset).await;
+                }
+
+                // 写入文件，中途断开时保留 .part 以便下次续传；摘要校验通过之后才落到最终位置
+                let mut digest = None;
+                let result = match write_field_to_part(
+                    field,
+                    &part_path,
+                    offset,
+                    size,
+                    &mut tracker,
+                    batch_tracker.as_deref(),
+                )
+                .await
+                {
+                    Ok(actual) => {
+                        digest = Some(actual.clone());
+                        let expected = match multipart.next_field().await {
+                            Ok(Some(field)) if field.name() == Some("sha256") => field.text().await.ok(),
+                            _ => None,
+                        };
+                        if expected.as_deref() != Some(actual.as_str()) {
+                            // 内容已经损坏，续传也救不回来，删掉 .part 让下次从头传
+                            let _ = fs::remove_file(&part_path).await;
+                            let error = format!("Checksum mismatch for {}", name);
+                            eprintln!("{}", error);
+                            tracker.set_status("verification_failed", Some(error.clone())).await;
+                            if let Some(batch_tracker) = &batch_tracker {
+                                batch_tracker
+                                    .lock()
+                                    .await
+                                    .set_status("verification_failed", Some(error.clone()))
+                                    .await;
+                            }
+                            state.events.emit("file-verification-failed", serde_json::json!({
+                                "sender": message.sender,
+                                "fileName": name,
+                                "transferId": transfer_id,
+                                "expected": expected,
+                                "actual": actual,
+                                "timestamp": message.timestamp
+                            }));
+                            record_history(&state.history, HistoryEntry {
+                                sha256: Some(actual),
+                                duration_ms: started.elapsed().as_millis() as u64,
+                                outcome: "verification_failed".to_string(),
+                                error: Some(error),
+                                ..history_entry("receive", "file", &message.sender, &name, size)
+                            })
+                            .await;
+                            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+                        }
+                        finalize_file(&part_path, &file_path, conflict_policy).await
+                    }
+                    Err(e) => Err(e),
+                };
+                let final_path = match result {
+                    Ok(final_path) => {
+                        match &final_path {
+                            Some(_) => tracker.finish(&Ok(())).await,
+                            None => tracker.set_status("skipped", None).await,
+                        }
+                        final_path
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to write file: {}", e);
+                        record_history(&state.history, HistoryEntry {
+                            duration_ms: started.elapsed().as_millis() as u64,
+                            outcome: "failed".to_string(),
+                            error: Some(e.to_string()),
+                            ..history_entry("receive", "file", &message.sender, &name, size)
+                        })
+                        .await;
+                        let result = Err(e);
+                        tracker.finish(&result).await;
+                        if let Some(batch_tracker) = &batch_tracker {
+                            batch_tracker.lock().await.finish(&result).await;
+                        }
+                        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                    }
+                };
+
+                // 清单里的文件全部收到后，这次请求就结束了
+                let mut approved_requests = state.approved_requests.lock().await;
+                let finished = match approved_requests.get_mut(&request_id) {
+                    Some(approved) => {
+                        approved.files.remove(&name);
+                        approved.last_activity = Instant::now();
+                        approved.files.is_empty()
+                    }
+                    None => false,
+                };
+                if finished {
+                    approved_requests.remove(&request_id);
+                    if let Some(batch_tracker) = &batch_tracker {
+                        batch_tracker.lock().await.finish(&Ok(())).await;
+                    }
+                }
+                drop(approved_requests);
+
+                record_history(&state.history, HistoryEntry {
+                    path: final_path.as_ref().map(|path| path.to_string_lossy().to_string()),
+                    sha256: digest,
+                    duration_ms: started.elapsed().as_millis() as u64,
+                    outcome: if final_path.is_some() { "completed" } else { "skipped" }.to_string(),
+                    ..history_entry("receive", "file", &message.sender, &name, size)
+                })
+                .await;
+
+                let response = match &final_path {
+                    Some(final_path) => {
+                        // 发送事件到前端
+                        state.events.emit("file-received", serde_json::json!({
+                            "sender": message.sender,
+                            "fileName": name,
+                            "filePath": final_path.to_string_lossy(),
+                            "fileSize": size,
+                            "mimeType": mime_type,
+                            "timestamp": message.timestamp
+                        }));
+                        eprintln!("File received: {} from {}", name, message.sender.name);
+                        serde_json::json!({
+                            "status": "success",
+                            "message": "File received successfully"
+                        })
+                    }
+                    None => {
+                        eprintln!("Skipped {} from {}: file already exists", name, message.sender.name);
+                        serde_json::json!({
+                            "status": "skipped",
+                            "message": "File already exists"
+                        })
+                    }
+                };
+                if finished {
+                    state.events.emit("file-request-completed", serde_json::json!({
+                        "requestId": request_id
+                    }));
+                }
+
+                return Ok(Json(response));
+            }
+            _ => {}
+        }
+    }
+
+    Err(StatusCode::BAD_REQUEST)
+}
+
+// 从 offset 开始逐块追加写盘，内存占用只与单个分块大小有关；返回整个文件的 SHA-256
+async fn write_field_to_part(
+    mut field: axum::extract::multipart::Field<'_>,
+    part_path: &Path,
+    offset: u64,
+    expected_size: u64,
+    tracker: &mut ProgressTracker,
+    batch_tracker: Option<&Mutex<ProgressTracker>>,
+) -> Result<String> {
+    if let Some(parent) = part_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    let (mut file, mut hasher) = if offset == 0 {
+        (fs::File::create(part_path).await?, Sha256::new())
     } else {
-        Err(StatusCode::BAD_REQUEST)
+        (
+            fs::OpenOptions::new().append(true).open(part_path).await?,
+            prefix_hasher(part_path, offset).await?,
+        )
+    };
+    let mut written = offset;
+
+    while let Some(chunk) = field.chunk().await? {
+        written += chunk.len() as u64;
+        if written > expected_size {
+            drop(file);
+            let _ = fs::remove_file(part_path).await;
+            return Err(anyhow::anyhow!("Received more than {} bytes", expected_size));
+        }
+        file.write_all(&chunk).await?;
+        hasher.update(&chunk);
+        tracker.advance(chunk.len() as u64).await;
+        if let Some(batch_tracker) = batch_tracker {
+            batch_tracker.lock().await.advance(chunk.len() as u64).await;
+        }
+    }
+    file.flush().await?;
+
+    if written != expected_size {
+        return Err(anyhow::anyhow!(
+            "Incomplete transfer: expected {} bytes, got {}",
+            expected_size,
+            written
+        ));
     }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+async fn get_resume_info(
+    AxumState(state): AxumState<SharedState>,
+    AxumPath(transfer_id): AxumPath<String>,
+) -> Result<Json<ResumeInfo>, StatusCode> {
+    if !is_valid_transfer_id(&transfer_id) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let receive_dir = state.settings.lock().await.receive_dir();
+    let part_path = partial_path(&receive_dir, &transfer_id);
+
+    let offset = fs::metadata(&part_path).await.map(|m| m.len()).unwrap_or(0);
+    let sha256 = if offset > 0 {
+        Some(hash_prefix(&part_path, offset).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?)
+    } else {
+        None
+    };
+
+    Ok(Json(ResumeInfo { transfer_id, offset, sha256 }))
+}
+
+// 按冲突策略把写完的 .part 移到最终位置，返回实际路径；跳过时返回 None。
+// 先用 create_new 占住目标文件名再 rename 覆盖占位文件，避免两个传输抢同一个名字
+async fn finalize_file(part_path: &Path, file_path: &Path, policy: ConflictPolicy) -> Result<Option<PathBuf>> {
+    if policy == ConflictPolicy::Overwrite {
+        move_file(part_path, file_path).await?;
+        return Ok(Some(file_path.to_path_buf()));
+    }
+
+    let stem = file_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = file_path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut candidate = file_path.to_path_buf();
+    let mut n = 0;
+    loop {
+        match fs::OpenOptions::new().write(true).create_new(true).open(&candidate).await {
+            Ok(_) => {
+                // 移动失败时把占位的空文件删掉，不然下次会被当成同名文件
+                if let Err(e) = move_file(part_path, &candidate).await {
+                    let _ = fs::remove_file(&candidate).await;
+                    return Err(e);
+                }
+                return Ok(Some(candidate));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                // 询问阶段之后才出现的同名文件，跳过策略下同样放弃
+                if policy == ConflictPolicy::Skip {
+                    fs::remove_file(part_path).await?;
+                    return Ok(None);
+                }
+                n += 1;
+                candidate = file_path.with_file_name(format!("{} ({}){}", stem, n, extension));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+// 分流目录可能和续传目录不在同一个文件系统上，rename 失败时先复制到目标旁边的临时文件再改名
+async fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+
+    let file_name = to.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let temp_path = to.with_file_name(format!(".{}.landrop-tmp", file_name));
+    if let Err(e) = fs::copy(from, &temp_path).await {
+        let _ = fs::remove_file(&temp_path).await;
+        return Err(e.into());
+    }
+    fs::rename(&temp_path, to).await?;
+    fs::remove_file(from).await?;
+    Ok(())
+}
+
+// 已存在的中间目录不能是符号链接，否则可能被引到目标目录之外；也不能写进续传目录。
+// relative_path 已经过 sanitize_relative_path，不含 .. 和绝对路径
+async fn is_safe_destination(target_dir: &Path, relative_path: &Path) -> bool {
+    if relative_path.starts_with(PARTIAL_DIR) {
+        return false;
+    }
+
+    let mut current = target_dir.to_path_buf();
+    for component in relative_path.components() {
+        current.push(component);
+        match fs::symlink_metadata(&current).await {
+            Ok(metadata) if metadata.file_type().is_symlink() => return false,
+            Ok(_) => continue,
+            Err(_) => break,
+        }
+    }
+    true
+}
+
+// 展开文件夹，得到所有文件及其相对路径；文件夹本身的名字作为相对路径的第一级。
+// 清单里只有文件，空文件夹不会传过去
+fn collect_files(paths: &[String]) -> Result<Vec<LocalFile>> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        let path = PathBuf::from(path);
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        let metadata = std::fs::metadata(&path)?;
+        if !metadata.is_dir() {
+            files.push(LocalFile {
+                path,
+                relative_path: name,
+                size: metadata.len(),
+            });
+            continue;
+        }
+
+        let mut pending = vec![(path, name)];
+        while let Some((dir, relative_dir)) = pending.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let relative_path = format!("{}/{}", relative_dir, entry.file_name().to_string_lossy());
+                let file_type = entry.file_type()?;
+
+                if file_type.is_dir() {
+                    pending.push((entry.path(), relative_path));
+                } else if file_type.is_file() {
+                    files.push(LocalFile {
+                        path: entry.path(),
+                        relative_path,
+                        size: entry.metadata()?.len(),
+                    });
+                } else if file_type.is_symlink() {
+                    // 只跟随指向文件的符号链接，避免目录链接造成循环
+                    if let Ok(metadata) = std::fs::metadata(entry.path()) {
+                        if metadata.is_file() {
+                            files.push(LocalFile {
+                                path: entry.path(),
+                                relative_path,
+                                size: metadata.len(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // 接收方按相对路径对照清单，不同位置的同名文件或文件夹会变成同一项，只有一个能传过去
+    let mut relative_paths = HashSet::new();
+    for file in &files {
+        if !relative_paths.insert(file.relative_path.as_str()) {
+            return Err(anyhow::anyhow!(
+                "More than one file would be sent as {}; rename one of them or send them separately",
+                file.relative_path
+            ));
+        }
+    }
+
+    Ok(files)
+}
+
+async fn probe_address(address: SocketAddr) -> Result<()> {
+    match tokio::time::timeout(CONNECT_TIMEOUT, tokio::net::TcpStream::connect(address)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(anyhow::anyhow!("{}: {}", address, e)),
+        Err(_) => Err(anyhow::anyhow!("{}: timed out", address)),
+    }
+}
+
+async fn bind_with_fallback(port: u16) -> Result<tokio::net::TcpListener> {
+    for candidate in (0..PORT_FALLBACK_ATTEMPTS).filter_map(|i| port.checked_add(i)) {
+        match bind_dual_stack(candidate) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => eprintln!("Failed to bind port {}: {}", candidate, e),
+        }
+    }
+
+    Ok(bind_dual_stack(0)?)
+}
+
+// 监听 [::] 并关闭 IPV6_V6ONLY，同一个端口同时接受 IPv4 和 IPv6；系统没有 IPv6 时退回 0.0.0.0
+fn bind_dual_stack(port: u16) -> std::io::Result<tokio::net::TcpListener> {
+    let socket = match bind_tcp(Domain::IPV6, SocketAddr::from((Ipv6Addr::UNSPECIFIED, port))) {
+        Err(e) if e.kind() != std::io::ErrorKind::AddrInUse => {
+            bind_tcp(Domain::IPV4, SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))?
+        }
+        result => result?,
+    };
+    socket.set_nonblocking(true)?;
+    tokio::net::TcpListener::from_std(socket.into())
+}
+
+fn bind_tcp(domain: Domain, address: SocketAddr) -> std::io::Result<Socket> {
+    let socket = Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?;
+    if domain == Domain::IPV6 {
+        socket.set_only_v6(false)?;
+    }
+    // 与 tokio 的 TcpListener::bind 一致，Unix 上允许重启后立即复用处于 TIME_WAIT 的端口
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(1024)?;
+    Ok(socket)
+}
+
+// fe80::1%3 -> fe80-0-0-0-0-0-0-1-s3.landrop.invalid
+fn scoped_host(address: &SocketAddrV6) -> String {
+    let segments: Vec<String> = address.ip().segments().iter().map(|segment| format!("{:x}", segment)).collect();
+    format!("{}-s{}.landrop.invalid", segments.join("-"), address.scope_id())
+}
+
+fn history_entry(direction: &str, kind: &str, peer: &Device, name: &str, size: u64) -> HistoryEntry {
+    HistoryEntry {
+        id: uuid::Uuid::new_v4().to_string(),
+        direction: direction.to_string(),
+        kind: kind.to_string(),
+        peer_id: peer.id.clone(),
+        peer_name: peer.name.clone(),
+        name: name.to_string(),
+        size,
+        outcome: "completed".to_string(),
+        timestamp: pairing::now_secs(),
+        ..Default::default()
+    }
+}
+
+// 历史记录写失败不影响传输本身
+async fn record_history(history: &Mutex<History>, entry: HistoryEntry) {
+    if let Err(e) = history.lock().await.append(&entry) {
+        eprintln!("Failed to record history: {}", e);
+    }
+}
+
+fn make_transfer_id(file_path: &str, size: u64, modified: u64, target_device: &Device) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(file_path.as_bytes());
+    hasher.update(size.to_le_bytes());
+    hasher.update(modified.to_le_bytes());
+    // 用设备 id 而不是地址，换了网卡或地址之后仍能续传
+    hasher.update(target_device.id.as_bytes());
+    format!("{:x}", hasher.finalize())[..32].to_string()
+}
+
+// transfer_id 会被拼进文件名，只接受十六进制字符
+fn is_valid_transfer_id(transfer_id: &str) -> bool {
+    !transfer_id.is_empty() && transfer_id.len() <= 64 && transfer_id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn partial_path(receive_dir: &Path, transfer_id: &str) -> PathBuf {
+    receive_dir.join(PARTIAL_DIR).join(format!("{}.part", transfer_id))
+}
+
+async fn hash_prefix(path: &Path, len: u64) -> Result<String> {
+    Ok(format!("{:x}", prefix_hasher(path, len).await?.finalize()))
+}
+
+async fn prefix_hasher(path: &Path, len: u64) -> Result<Sha256> {
+    let mut reader = fs::File::open(path).await?.take(len);
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(hasher)
 }
 
 async fn receive_text(
     AxumState(state): AxumState<SharedState>,
+    Extension(peer): Extension<AuthenticatedPeer>,
     Json(message): Json<TransferMessage>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
+    if message.sender.id != peer.0 {
+        return Err(StatusCode::FORBIDDEN);
+    }
     if let TransferData::Text { content } = message.data {
+        let entry = history_entry("receive", "text", &message.sender, &history::text_preview(&content), content.len() as u64);
+        record_history(&state.history, entry).await;
+
         // 发送事件到前端
-        let _ = state.app_handle.emit_all("text-received", serde_json::json!({
+        state.events.emit("text-received", serde_json::json!({
             "sender": message.sender,
             "content": content,
             "timestamp": message.timestamp
         }));
 
-        println!("Text received from {}: {}", message.sender.name, content);
+        eprintln!("Text received from {}: {}", message.sender.name, content);
 
         Ok(Json(serde_json::json!({
             "status": "success",
@@ -220,5 +1912,84 @@ async fn ping() -> Json<serde_json::Value> {
 }
 
 async fn get_device_info(AxumState(state): AxumState<SharedState>) -> Json<Device> {
-    Json(state.device)
-} 
\ No newline at end of file
+    Json(state.device.lock().await.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 每个用例一个独立的临时目录，里面放一个待移动的续传文件
+    fn setup(existing: Option<&str>) -> (PathBuf, PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("landrop-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let part_path = dir.join("upload.part");
+        std::fs::write(&part_path, "new").unwrap();
+        let file_path = dir.join("report.txt");
+        if let Some(content) = existing {
+            std::fs::write(&file_path, content).unwrap();
+        }
+        (dir, part_path, file_path)
+    }
+
+    #[tokio::test]
+    async fn moves_into_place_without_conflict() {
+        for policy in [ConflictPolicy::Rename, ConflictPolicy::Overwrite, ConflictPolicy::Skip] {
+            let (dir, part_path, file_path) = setup(None);
+            let result = finalize_file(&part_path, &file_path, policy).await.unwrap();
+            assert_eq!(result, Some(file_path.clone()));
+            assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "new");
+            assert!(!part_path.exists());
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn rename_picks_next_free_name() {
+        let (dir, part_path, file_path) = setup(Some("old"));
+        std::fs::write(dir.join("report (1).txt"), "older").unwrap();
+
+        let result = finalize_file(&part_path, &file_path, ConflictPolicy::Rename).await.unwrap();
+        assert_eq!(result, Some(dir.join("report (2).txt")));
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "old");
+        assert_eq!(std::fs::read_to_string(dir.join("report (2).txt")).unwrap(), "new");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ask_without_choice_renames() {
+        let (dir, part_path, file_path) = setup(Some("old"));
+        let result = finalize_file(&part_path, &file_path, ConflictPolicy::Ask).await.unwrap();
+        assert_eq!(result, Some(dir.join("report (1).txt")));
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "old");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn overwrite_replaces_existing_file() {
+        let (dir, part_path, file_path) = setup(Some("old"));
+        let result = finalize_file(&part_path, &file_path, ConflictPolicy::Overwrite).await.unwrap();
+        assert_eq!(result, Some(file_path.clone()));
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "new");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn skip_keeps_existing_file_and_drops_upload() {
+        let (dir, part_path, file_path) = setup(Some("old"));
+        let result = finalize_file(&part_path, &file_path, ConflictPolicy::Skip).await.unwrap();
+        assert_eq!(result, None);
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "old");
+        assert!(!part_path.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_move_leaves_no_placeholder() {
+        let (dir, part_path, file_path) = setup(None);
+        std::fs::remove_file(&part_path).unwrap();
+        assert!(finalize_file(&part_path, &file_path, ConflictPolicy::Rename).await.is_err());
+        assert!(!file_path.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6};
+use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Device {
@@ -10,33 +11,170 @@ pub struct Device {
     pub device_type: String,
     pub os: String,
     pub last_seen: u64,
+    // TLS 证书指纹，连接时据此固定证书；明文模式下为空
+    #[serde(default)]
+    pub cert_fingerprint: Option<String>,
+    // 本机所有网卡的地址；收到的一方会把实际来源地址放在 ip，这里作为备选
+    #[serde(default)]
+    pub addresses: Vec<IpAddr>,
+    // 本机到达对方 IPv6 链路本地地址要走的网卡编号，只在本机有意义，由发现服务填写
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope_id: Option<u32>,
+    // 由发现服务按本机收到广播的时间计算，对方自报的值没有意义
+    #[serde(default)]
+    pub presence: Presence,
+    // 最近一次 Ping 的往返时间（毫秒），只在本机有意义，由发现服务填写
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Presence {
+    #[default]
+    Online,
+    // 错过了几次广播，可能已经离开
+    Stale,
+    Offline,
 }
 
 impl Device {
     pub fn current() -> anyhow::Result<Self> {
-        let hostname = hostname::get()
-            .map_err(|e| anyhow::anyhow!("Failed to get hostname: {}", e))?
-            .to_string_lossy()
-            .to_string();
-        
-        let local_ip = local_ip_address::local_ip()
-            .map_err(|e| anyhow::anyhow!("Failed to get local IP: {}", e))?;
+        let name = crate::config::device_name()?;
         
         let os = std::env::consts::OS.to_string();
+
+        let interface_addresses: Vec<IpAddr> = local_ip_address::list_afinet_netifas()
+            .map(|interfaces| {
+                interfaces
+                    .into_iter()
+                    .map(|(_, ip)| ip)
+                    .filter(|ip| !ip.is_loopback())
+                    .collect()
+            })
+            .unwrap_or_default();
+        // IPv6 链路本地地址只有收到报文的一方知道该走哪块网卡，这里不公布，由 observed_at 记录
+        let addresses: Vec<IpAddr> = interface_addresses
+            .iter()
+            .copied()
+            .filter(|ip| !is_link_local(ip))
+            .collect();
+
+        // local_ip 只找 IPv4，纯 IPv6 网络上退回 IPv6 地址，再退回任意一块网卡的地址。
+        // 收到的一方会用实际来源地址替换这里的 ip，所以连网卡都没有时用回环地址占位，不影响服务启动
+        let local_ip = local_ip_address::local_ip()
+            .or_else(|_| local_ip_address::local_ipv6())
+            .ok()
+            .or_else(|| addresses.first().copied())
+            .or_else(|| interface_addresses.first().copied())
+            .unwrap_or(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST));
         
         Ok(Device {
-            id: uuid::Uuid::new_v4().to_string(),
-            name: hostname,
+            id: crate::config::device_id(),
+            name,
             ip: local_ip,
-            port: 8080,
+            port: crate::config::transfer_port(),
             device_type: "desktop".to_string(),
             os,
             last_seen: std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
+            cert_fingerprint: crate::tls::local_fingerprint(),
+            addresses,
+            scope_id: None,
+            presence: Presence::Online,
+            latency_ms: None,
         })
     }
+
+    // 连接时依次考虑的地址：先 ip，再其余公布的地址
+    pub fn candidate_addresses(&self) -> Vec<IpAddr> {
+        let mut candidates = vec![self.ip];
+        for ip in &self.addresses {
+            if !candidates.contains(ip) {
+                candidates.push(*ip);
+            }
+        }
+        candidates
+    }
+
+    // 对方自报的 ip 可能是 Docker 网桥或 VPN 的地址，以实际收到报文的来源地址为准，自报的地址留作备选。
+    // 双栈网络上同一条广播会从 IPv4 和 IPv6 各收到一次，所以首选地址只被同一协议族的来源替换，
+    // 收到过的链路本地地址和网卡编号也一直保留，免得设备信息来回变化
+    pub fn observed_at(mut self, source: SocketAddr, known: Option<&Device>) -> Self {
+        let advertised = self.candidate_addresses();
+        let mut link_local: Vec<IpAddr> = known
+            .map(|known| known.addresses.iter().copied().filter(is_link_local).collect())
+            .unwrap_or_default();
+        self.scope_id = known.and_then(|known| known.scope_id);
+        if let SocketAddr::V6(v6) = source {
+            if v6.scope_id() != 0 && is_link_local(&source.ip()) {
+                link_local.retain(|ip| *ip != source.ip());
+                link_local.insert(0, source.ip());
+                self.scope_id = Some(v6.scope_id());
+            }
+        }
+
+        self.ip = match known {
+            Some(known) if known.ip.is_ipv4() != source.ip().is_ipv4() => known.ip,
+            _ => source.ip(),
+        };
+        let mut addresses = vec![self.ip];
+        for ip in std::iter::once(source.ip()).chain(advertised).chain(link_local) {
+            if !addresses.contains(&ip) {
+                addresses.push(ip);
+            }
+        }
+        self.addresses = addresses;
+        self
+    }
+
+    // 没有来源地址时（例如 mDNS 解析出的记录）沿用已知的首选地址和网卡编号，地址取并集
+    pub fn merged_with(mut self, known: Option<&Device>) -> Self {
+        let Some(known) = known else {
+            return self;
+        };
+        let mut addresses = known.candidate_addresses();
+        for ip in self.candidate_addresses() {
+            if !addresses.contains(&ip) {
+                addresses.push(ip);
+            }
+        }
+        self.ip = known.ip;
+        self.addresses = addresses;
+        self.scope_id = known.scope_id;
+        self
+    }
+
+    // 链路本地地址要带上网卡编号才能连接
+    pub fn socket_address(&self, ip: IpAddr) -> SocketAddr {
+        match (ip, self.scope_id) {
+            (IpAddr::V6(v6), Some(scope_id)) if is_link_local(&ip) => {
+                SocketAddrV6::new(v6, self.port, 0, scope_id).into()
+            }
+            _ => SocketAddr::new(ip, self.port),
+        }
+    }
+}
+
+// fe80::/10
+pub fn is_link_local(ip: &IpAddr) -> bool {
+    matches!(ip, IpAddr::V6(v6) if (v6.segments()[0] & 0xffc0) == 0xfe80)
+}
+
+// 两次设备列表之间的变化，removed 只带设备 id
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceDelta {
+    pub added: Vec<Device>,
+    pub removed: Vec<String>,
+    pub changed: Vec<Device>,
+}
+
+impl DeviceDelta {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -51,22 +189,80 @@ pub struct TransferMessage {
 #[serde(tag = "type")]
 pub enum TransferData {
     Text { content: String },
+    // 文件内容不在消息里，而是作为 multipart 的 file 字段流式传输
     File { 
         name: String, 
         size: u64, 
         mime_type: String,
-        data: Vec<u8> 
+        // 断点续传：同一文件同一目标的 transfer_id 保持不变，offset 为本次开始的字节位置
+        transfer_id: String,
+        #[serde(default)]
+        offset: u64,
+        // 接收方同意的 FileRequest 的 request_id
+        request_id: String,
     },
+    // 发送文件前先询问接收方，同意后才开始上传
     FileRequest { 
+        request_id: String,
         name: String, 
-        size: u64 
+        size: u64,
+        file_count: u64,
+        // 本次要发送的全部文件及其相对路径
+        files: Vec<ManifestEntry>,
     },
 }
 
+// 接收的文件与已有文件重名时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    #[default]
+    Rename,
+    Overwrite,
+    Skip,
+    Ask,
+}
+
+// 手动添加的设备，组播到不了的网段靠它直接连接
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StaticPeer {
+    // IP 地址或主机名
+    pub host: String,
+    pub port: u16,
+}
+
+// 接收文件的分流规则：填写了的条件全部满足才算命中，按顺序取第一条命中的规则
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingRule {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub sender_id: Option<String>,
+    // 完整类型如 image/png，或 image/* 匹配整个大类
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    // 不带点，不区分大小写
+    #[serde(default)]
+    pub extension: Option<String>,
+    pub directory: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub path: String, // 以 / 分隔的相对路径
+    pub size: u64,
+    // 发送方判断的类型，和之后传文件时带的一致；旧版本没有这个字段时接收方按文件名推断
+    #[serde(default)]
+    pub mime_type: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiscoveryMessage {
     pub device: Device,
     pub message_type: DiscoveryMessageType,
+    // Ping 带上序号，Pong 原样带回，发送方据此算出往返时间
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -77,17 +273,86 @@ pub enum DiscoveryMessageType {
     Pong,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumeInfo {
+    pub transfer_id: String,
+    pub offset: u64,
+    pub sha256: Option<String>, // 已接收前缀的哈希，offset 为 0 时为空
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransferProgress {
     pub transfer_id: String,
     pub bytes_transferred: u64,
     pub total_bytes: u64,
     pub percentage: f32,
+    pub bytes_per_second: f64,
+    pub eta_seconds: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransferStatus {
     pub transfer_id: String,
+    pub file_name: String,
+    pub direction: String, // "send", "receive"
     pub status: String, // "pending", "transferring", "completed", "failed"
+    pub bytes_transferred: u64,
+    pub total_bytes: u64,
     pub error: Option<String>,
-} 
\ No newline at end of file
+    // 结束的时间，保留一段时间后从列表里清掉
+    #[serde(skip)]
+    pub finished_at: Option<std::time::Instant>,
+} 
+// 一条收发记录；outcome 与 TransferStatus.status 取值一致，另有 declined 和 expired
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    pub direction: String,
+    // file 或 text
+    pub kind: String,
+    pub peer_id: String,
+    pub peer_name: String,
+    // 文件的相对路径，或文本内容的开头
+    pub name: String,
+    pub size: u64,
+    pub path: Option<String>,
+    pub sha256: Option<String>,
+    pub duration_ms: u64,
+    pub outcome: String,
+    pub error: Option<String>,
+    pub timestamp: u64,
+}
+
+// 查询条件，未填写的字段不参与过滤
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryQuery {
+    pub search: Option<String>,
+    pub direction: Option<String>,
+    pub kind: Option<String>,
+    pub peer_id: Option<String>,
+    pub outcome: Option<String>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairedDevice {
+    pub id: String,
+    pub name: String,
+    pub paired_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairRequest {
+    pub device: Device,
+    pub public_key: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairConfirm {
+    pub device_id: String,
+    pub proof: String,
+}
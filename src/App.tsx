@@ -1,13 +1,13 @@
 import { useState, useEffect } from 'react';
 import { invoke } from '@tauri-apps/api/tauri';
 import { listen } from '@tauri-apps/api/event';
-import { open } from '@tauri-apps/api/dialog';
+import { ask, open } from '@tauri-apps/api/dialog';
 import { Wifi, WifiOff, Send, FileText, Users } from 'lucide-react';
 import DeviceList from './components/DeviceList';
 import TextTransfer from './components/TextTransfer';
 import FileTransfer from './components/FileTransfer';
 import Notifications from './components/Notifications';
-import { Device, Notification } from './types';
+import { ConflictPolicy, Device, DeviceDelta, Notification, PairedDevice } from './types';
 
 function App() {
   const [isDiscovering, setIsDiscovering] = useState(false);
@@ -32,6 +32,40 @@ function App() {
       });
     });
 
+    // 监听文件校验失败事件，内容损坏的文件不会被保存
+    const unlistenVerification = listen<any>('file-verification-failed', (event) => {
+      const { sender, fileName } = event.payload;
+      addNotification({
+        id: Date.now().toString(),
+        type: 'error',
+        title: '文件校验失败',
+        message: `从 ${sender.name} 接收的 ${fileName} 内容不完整或已损坏，请重新发送`,
+        timestamp: Date.now(),
+      });
+    });
+
+    // 传输服务启动失败时提示用户，否则只能在终端里看到
+    const unlistenServer = listen<string>('server-error', (event) => {
+      addNotification({
+        id: Date.now().toString(),
+        type: 'error',
+        title: '传输服务启动失败',
+        message: event.payload,
+        timestamp: Date.now(),
+      });
+    });
+
+    // 监听设备列表变化，只包含新增、移除和信息变化的设备
+    const unlistenDevices = listen<DeviceDelta>('devices-updated', (event) => {
+      const { added, removed, changed } = event.payload;
+      setDevices(prev => {
+        const updated = new Map(prev.map(device => [device.id, device]));
+        removed.forEach(id => updated.delete(id));
+        [...added, ...changed].forEach(device => updated.set(device.id, device));
+        return Array.from(updated.values());
+      });
+    });
+
     // 监听文本接收事件
     const unlistenText = listen<any>('text-received', (event) => {
       const { sender, content } = event.payload;
@@ -44,9 +78,41 @@ function App() {
       });
     });
 
+    // 监听文件接收请求，由用户决定是否接收
+    const unlistenRequest = listen<any>('file-request', async (event) => {
+      const { requestId, sender, fileName, fileCount, conflicts, conflictPolicy } = event.payload;
+      const description = fileCount > 1 ? `${fileName} 等 ${fileCount} 个文件` : fileName;
+      const accept = await ask(`${sender.name} 想要发送 ${description}，是否接收？`, { title: '文件接收请求' });
+
+      // 设置为每次询问时，让用户决定已存在的同名文件是覆盖还是自动重命名
+      let policy: ConflictPolicy | null = null;
+      if (accept && conflictPolicy === 'ask' && conflicts.length > 0) {
+        const overwrite = await ask(`${conflicts.length} 个文件已存在，是否覆盖？选择否将自动重命名。`, { title: '文件已存在' });
+        policy = overwrite ? 'overwrite' : 'rename';
+      }
+
+      invoke('respond_file_request', { requestId, accept, conflictPolicy: policy }).catch((error) => {
+        console.error('Failed to respond to file request:', error);
+      });
+    });
+
+    // 监听配对请求，让用户核对两边屏幕上的确认码
+    const unlistenPair = listen<any>('pair-request', async (event) => {
+      const { pairingId, device, code } = event.payload;
+      const accept = await ask(`${device.name} 请求配对，确认码为 ${code}，是否与对方屏幕上显示的一致？`, { title: '设备配对' });
+      invoke('respond_pairing', { pairingId, accept }).catch((error) => {
+        console.error('Failed to confirm pairing:', error);
+      });
+    });
+
     return () => {
       unlistenFile.then(f => f());
+      unlistenVerification.then(f => f());
+      unlistenServer.then(f => f());
+      unlistenPair.then(f => f());
       unlistenText.then(f => f());
+      unlistenRequest.then(f => f());
+      unlistenDevices.then(f => f());
     };
   }, []);
 
@@ -57,6 +123,15 @@ function App() {
     }, 5000);
   };
 
+  // 立即 Ping 一轮，结果由 devices-updated 事件推送
+  const refreshDevices = async () => {
+    try {
+      await invoke('refresh_devices');
+    } catch (error) {
+      console.error('Failed to refresh devices:', error);
+    }
+  };
+
   const toggleDiscovery = async () => {
     try {
       if (isDiscovering) {
@@ -66,37 +141,43 @@ function App() {
       } else {
         await invoke('start_discovery');
         setIsDiscovering(true);
-        // 定期更新设备列表
-        const interval = setInterval(async () => {
-          try {
-            const deviceList = await invoke<Device[]>('get_devices');
-            setDevices(deviceList);
-          } catch (error) {
-            console.error('Failed to get devices:', error);
-          }
-        }, 2000);
-
-        // 清理定时器
-        setTimeout(() => clearInterval(interval), 0);
+        // 之后的变化由 devices-updated 事件推送
+        setDevices(await invoke<Device[]>('get_devices'));
       }
     } catch (error) {
       console.error('Failed to toggle discovery:', error);
     }
   };
 
+  // 未配对的设备先走配对流程，两边确认码一致才继续发送
+  const ensurePaired = async (device: Device) => {
+    const paired = await invoke<PairedDevice[]>('list_paired_devices');
+    if (paired.some(p => p.id === device.id)) {
+      return;
+    }
+
+    const code = await invoke<string>('pair_device', { targetDevice: device });
+    const accept = await ask(`与 ${device.name} 配对，确认码为 ${code}，是否与对方屏幕上显示的一致？`, { title: '设备配对' });
+    const success = await invoke<boolean>('confirm_pairing', { deviceId: device.id, accept });
+    if (!success) {
+      throw new Error('Pairing was not confirmed');
+    }
+  };
+
   const sendFile = async (device: Device) => {
     try {
+      await ensurePaired(device);
       const selected = await open({
-        multiple: false,
+        multiple: true,
         filters: [{
           name: 'All Files',
           extensions: ['*']
         }]
       });
 
-      if (selected && typeof selected === 'string') {
-        await invoke('send_file', {
-          filePath: selected,
+      if (selected && selected.length > 0) {
+        await invoke('send_paths', {
+          paths: Array.isArray(selected) ? selected : [selected],
           targetDevice: device
         });
         
@@ -122,6 +203,7 @@ function App() {
 
   const sendText = async (device: Device, text: string) => {
     try {
+      await ensurePaired(device);
       await invoke('send_text', {
         text,
         targetDevice: device
@@ -205,6 +287,7 @@ function App() {
             devices={devices}
             isDiscovering={isDiscovering}
             onSendFile={sendFile}
+            onRefresh={refreshDevices}
           />
         )}
         {activeTab === 'text' && (
@@ -1,14 +1,15 @@
 import { useState } from 'react';
-import { Monitor, Smartphone, Tablet, Laptop, Send, Circle } from 'lucide-react';
-import { Device } from '../types';
+import { Monitor, Smartphone, Tablet, Laptop, Send, Circle, RefreshCw } from 'lucide-react';
+import { Device, Presence } from '../types';
 
 interface DeviceListProps {
   devices: Device[];
   isDiscovering: boolean;
   onSendFile: (device: Device) => void;
+  onRefresh: () => void;
 }
 
-const DeviceList = ({ devices, isDiscovering, onSendFile }: DeviceListProps) => {
+const DeviceList = ({ devices, isDiscovering, onSendFile, onRefresh }: DeviceListProps) => {
   const [selectedDevice, setSelectedDevice] = useState<Device | null>(null);
 
   const getDeviceIcon = (deviceType: string, os: string) => {
@@ -41,6 +42,17 @@ const DeviceList = ({ devices, isDiscovering, onSendFile }: DeviceListProps) =>
     }
   };
 
+  const presenceStyles: Record<Presence, { color: string; label: string }> = {
+    online: { color: 'text-green-500', label: '在线' },
+    stale: { color: 'text-yellow-500', label: '可能已离开' },
+    offline: { color: 'text-gray-400', label: '离线' },
+  };
+
+  const formatLatency = (latency?: number) => {
+    if (latency === undefined) return null;
+    return latency < 1 ? '<1 ms' : `${latency} ms`;
+  };
+
   const formatLastSeen = (timestamp: number) => {
     const now = Date.now() / 1000;
     const diff = now - timestamp;
@@ -62,6 +74,13 @@ const DeviceList = ({ devices, isDiscovering, onSendFile }: DeviceListProps) =>
             <div className="flex items-center space-x-2 text-sm text-gray-500">
               <Circle className="animate-pulse text-green-500" size={8} />
               <span>正在搜索设备...</span>
+              <button
+                onClick={onRefresh}
+                className="flex items-center space-x-1 px-2 py-1 rounded text-primary-600 hover:bg-primary-50"
+              >
+                <RefreshCw size={14} />
+                <span>刷新</span>
+              </button>
             </div>
           )}
         </div>
@@ -105,15 +124,21 @@ const DeviceList = ({ devices, isDiscovering, onSendFile }: DeviceListProps) =>
               </div>
 
               <div className="flex items-center justify-between">
-                <div className="text-xs text-gray-500">
-                  {formatLastSeen(device.last_seen)}
+                <div className="flex items-center space-x-1 text-xs text-gray-500">
+                  <Circle className={presenceStyles[device.presence].color} fill="currentColor" size={8} />
+                  <span>
+                    {presenceStyles[device.presence].label}
+                    {device.latency_ms !== undefined && ` · ${formatLatency(device.latency_ms)}`}
+                    {' · '}{formatLastSeen(device.last_seen)}
+                  </span>
                 </div>
                 <button
                   onClick={(e) => {
                     e.stopPropagation();
                     onSendFile(device);
                   }}
-                  className="flex items-center space-x-1 px-3 py-1 text-sm bg-primary-100 text-primary-700 rounded-md hover:bg-primary-200 transition-colors"
+                  disabled={device.presence === 'offline'}
+                  className="flex items-center space-x-1 px-3 py-1 text-sm bg-primary-100 text-primary-700 rounded-md hover:bg-primary-200 transition-colors disabled:opacity-50 disabled:cursor-not-allowed"
                 >
                   <Send size={14} />
                   <span>发送文件</span>
@@ -147,6 +172,14 @@ const DeviceList = ({ devices, isDiscovering, onSendFile }: DeviceListProps) =>
                 <span className="text-gray-500">设备类型:</span>
                 <span className="ml-2 font-medium">{selectedDevice.device_type}</span>
               </div>
+              <div>
+                <span className="text-gray-500">状态:</span>
+                <span className="ml-2 font-medium">{presenceStyles[selectedDevice.presence].label}</span>
+              </div>
+              <div>
+                <span className="text-gray-500">延迟:</span>
+                <span className="ml-2 font-medium">{formatLatency(selectedDevice.latency_ms) ?? '未知'}</span>
+              </div>
               <div>
                 <span className="text-gray-500">最后在线:</span>
                 <span className="ml-2 font-medium">{formatLastSeen(selectedDevice.last_seen)}</span>
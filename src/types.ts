@@ -6,8 +6,15 @@ export interface Device {
   device_type: string;
   os: string;
   last_seen: number;
+  cert_fingerprint: string | null;
+  addresses: string[];
+  scope_id?: number;
+  presence: Presence;
+  latency_ms?: number;
 }
 
+export type Presence = 'online' | 'stale' | 'offline';
+
 export interface TransferMessage {
   message_type: string;
   sender: Device;
@@ -21,7 +28,16 @@ export interface TransferData {
   name?: string;
   size?: number;
   mime_type?: string;
-  data?: number[];
+  transfer_id?: string;
+  offset?: number;
+  request_id?: string;
+  file_count?: number;
+}
+
+export interface DeviceDelta {
+  added: Device[];
+  removed: string[];
+  changed: Device[];
 }
 
 export interface Notification {
@@ -37,4 +53,79 @@ export interface TransferProgress {
   bytes_transferred: number;
   total_bytes: number;
   percentage: number;
-} 
\ No newline at end of file
+  bytes_per_second: number;
+  eta_seconds: number | null;
+}
+
+export interface TransferStatus {
+  transfer_id: string;
+  file_name: string;
+  direction: 'send' | 'receive';
+  status: 'pending' | 'transferring' | 'completed' | 'failed' | 'skipped' | 'verification_failed';
+  bytes_transferred: number;
+  total_bytes: number;
+  error: string | null;
+}
+
+export interface PairedDevice {
+  id: string;
+  name: string;
+  paired_at: number;
+}
+
+export type ConflictPolicy = 'rename' | 'overwrite' | 'skip' | 'ask';
+
+export interface RoutingRule {
+  id: string;
+  sender_id: string | null;
+  mime_type: string | null;
+  extension: string | null;
+  directory: string;
+}
+
+export interface Settings {
+  allow_plaintext: boolean;
+  conflict_policy: ConflictPolicy;
+  receive_dir: string | null;
+  routing_rules: RoutingRule[];
+  transfer_port: number;
+  discovery_port: number;
+  udp_discovery: boolean;
+  mdns_discovery: boolean;
+  unicast_targets: string[];
+  static_peers: StaticPeer[];
+  device_name: string | null;
+}
+
+export interface StaticPeer {
+  host: string;
+  port: number;
+}
+
+export interface HistoryEntry {
+  id: string;
+  direction: 'send' | 'receive';
+  kind: 'file' | 'text';
+  peer_id: string;
+  peer_name: string;
+  name: string;
+  size: number;
+  path: string | null;
+  sha256: string | null;
+  duration_ms: number;
+  outcome: 'completed' | 'failed' | 'skipped' | 'verification_failed' | 'declined' | 'expired';
+  error: string | null;
+  timestamp: number;
+}
+
+export interface HistoryQuery {
+  search?: string;
+  direction?: 'send' | 'receive';
+  kind?: 'file' | 'text';
+  peer_id?: string;
+  outcome?: HistoryEntry['outcome'];
+  since?: number;
+  until?: number;
+  offset?: number;
+  limit?: number;
+}